@@ -22,7 +22,12 @@ use jni::objects::{JClass, JObject, JString, JValue};
 use jni::sys::{jint, jobject};
 use jni::JNIEnv;
 use nix::errno::Errno;
-use paperd_lib::{accept_connection, bind_socket, Error};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use paperd_lib::bind_abstract_socket;
+use paperd_lib::{
+    accept_connection, bind_socket_mode, Error, ABSTRACT_SOCKET_VAR, DEFAULT_SOCKET_MODE,
+    SOCKET_MODE_VAR,
+};
 
 use paperd_lib::{
     close_socket, create_socket, receive_message, send_message, Message, MessageHeader,
@@ -68,7 +73,23 @@ pub extern "system" fn Java_com_destroystokyo_paper_daemon_PaperDaemonJni_create
     };
 
     let sock = handle_syscall!(env, create_socket(), -1);
-    handle_syscall!(env, bind_socket(sock, sock_file_path.as_str()), -1);
+    match std::env::var(ABSTRACT_SOCKET_VAR) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Ok(name) => handle_syscall!(env, bind_abstract_socket(sock, name.as_str()), -1),
+        _ => {
+            // paperd checks the mode before starting the server, an invalid one can only come from
+            // starting the server some other way
+            let mode = std::env::var(SOCKET_MODE_VAR)
+                .ok()
+                .and_then(|mode| u32::from_str_radix(&mode, 8).ok())
+                .unwrap_or(DEFAULT_SOCKET_MODE);
+            handle_syscall!(
+                env,
+                bind_socket_mode(sock, sock_file_path.as_str(), mode),
+                -1
+            )
+        }
+    };
 
     return sock;
 }
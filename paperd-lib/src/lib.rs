@@ -21,7 +21,12 @@ use nix::sys::socket::{
     accept, bind, connect, listen, recv, send, setsockopt, socket, AddressFamily, MsgFlags,
     SockAddr, SockFlag, SockType, UnixAddr,
 };
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::sys::socket::{getsockname, getsockopt, sockopt::PeerCredentials};
+use nix::sys::stat::{fchmodat, FchmodatFlags, Mode};
 use nix::sys::time::{TimeVal, TimeValLike};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::unistd::getuid;
 use nix::unistd::{close, unlink};
 use nix::NixPath;
 use std::cmp::min;
@@ -50,8 +55,25 @@ pub struct Message {
 pub type Socket = RawFd;
 
 const META_SIZE: usize = 8;
-const MESSAGE_SIZE: usize = 1000;
+// The most data received per `recv` call. Status and timings responses fit into a single call at
+// this size, rather than taking dozens of them
+const MESSAGE_SIZE: usize = 64 * 1024;
 const TIMEOUT_MILLIS: u64 = 500;
+/// The largest message `receive_message` accepts. Real messages are far smaller than this, a
+/// message header claiming more than this is from a broken or malicious sender.
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 8 * 1024 * 1024;
+// A full socket buffer only means the other side hasn't caught up with reading yet, so sending is
+// given longer to make progress than receiving is
+const SEND_TIMEOUT_MILLIS: u64 = 5000;
+
+/// The environment variable paperd passes the name of the abstract socket in, for servers started
+/// with `--abstract-socket`. `createSocket` binds that name instead of the socket file when it's set.
+pub const ABSTRACT_SOCKET_VAR: &str = "PAPERD_ABSTRACT_SOCKET";
+/// The environment variable paperd passes `--socket-mode` in, as an octal number.
+pub const SOCKET_MODE_VAR: &str = "PAPERD_SOCKET_MODE";
+/// Connecting to a socket file takes write permission on it, so by default only the user the server
+/// runs as can send it commands.
+pub const DEFAULT_SOCKET_MODE: u32 = 0o600;
 
 pub fn create_socket() -> Result<Socket, Error> {
     let sock = syscall!(socket(
@@ -73,6 +95,25 @@ pub fn close_socket(sock: Socket) -> Result<(), Error> {
 }
 
 pub fn bind_socket(sock: Socket, file_path: &str) -> Result<(), Error> {
+    return bind_socket_mode(sock, file_path, DEFAULT_SOCKET_MODE);
+}
+
+/// Binds the socket to a socket file with the given permissions. Anyone who can write to the socket
+/// file can connect and run any command on the server, so giving the group or others write access
+/// effectively makes them server operators.
+pub fn bind_socket_mode(sock: Socket, file_path: &str, mode: u32) -> Result<(), Error> {
+    // If something still accepts connections on the socket file it belongs to another running
+    // server. Replacing it would leave that server unreachable, and clients meant for it would end
+    // up talking to us instead. Socket files nothing is listening on are left behind by servers
+    // which didn't shut down cleanly, those are safe to replace
+    if let Ok(other) = connect_socket(file_path) {
+        let _ = close_socket(other);
+        return Err(Error::Nix(
+            nix::Error::Sys(Errno::EADDRINUSE),
+            Some("bind".to_string()),
+        ));
+    }
+
     match syscall!(unlink(file_path)) {
         Ok(()) => Ok(()),
         // ENOENT == no such file or directory, we don't care if it doesn't exist
@@ -85,22 +126,75 @@ pub fn bind_socket(sock: Socket, file_path: &str) -> Result<(), Error> {
 
     syscall!(bind(sock, &sock_addr))?;
 
+    // bind creates the file with the process's umask applied, so set the permissions explicitly.
+    // Connections are refused until listen is called, so nobody can get in before this is done
+    let mode = Mode::from_bits_truncate(mode as nix::libc::mode_t);
+    syscall!(fchmodat(
+        None,
+        file_path,
+        mode,
+        FchmodatFlags::FollowSymlink
+    ))?;
+
     syscall!(listen(sock, 128))?;
 
     return Ok(());
 }
 
-pub fn connect_socket<P: ?Sized + NixPath>(sock_file: &P) -> Result<Socket, Error> {
-    let sock = create_socket()?;
+/// Binds the socket to a name in Linux's abstract socket namespace rather than to a file. Abstract
+/// sockets aren't tied to a file, so deleting or replacing the socket file doesn't leave the server
+/// unreachable. They aren't protected by file permissions either, so `accept_connection` turns
+/// away anyone connecting as another user.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn bind_abstract_socket(sock: Socket, name: &str) -> Result<(), Error> {
+    // Unlike socket files, abstract names disappear with the socket that bound them, so a name
+    // which is still in use always belongs to another running server and bind fails on its own
+    let addr = UnixAddr::new_abstract(name.as_bytes())?;
+    let sock_addr = SockAddr::Unix(addr);
+
+    syscall!(bind(sock, &sock_addr))?;
 
+    syscall!(listen(sock, 128))?;
+
+    return Ok(());
+}
+
+/// The abstract socket name of the server with the given socket file, for `--abstract-socket`. It's
+/// a hash of the canonical path of the socket file, so it stays the same for as long as the path
+/// does, whether or not the file itself exists. The hash is FNV-1a, which unlike the standard
+/// library's hasher is guaranteed to never change between builds.
+pub fn abstract_socket_name(canonical_sock_file: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in canonical_sock_file {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    return format!("paperd-{:016x}", hash);
+}
+
+pub fn connect_socket<P: ?Sized + NixPath>(sock_file: &P) -> Result<Socket, Error> {
     let addr = UnixAddr::new(sock_file)?;
-    let socket_addr = SockAddr::Unix(addr);
+    return connect_addr(&SockAddr::Unix(addr));
+}
+
+/// Connects to a socket bound with `bind_abstract_socket`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn connect_abstract_socket(name: &str) -> Result<Socket, Error> {
+    let addr = UnixAddr::new_abstract(name.as_bytes())?;
+    return connect_addr(&SockAddr::Unix(addr));
+}
+
+fn connect_addr(socket_addr: &SockAddr) -> Result<Socket, Error> {
+    let sock = create_socket()?;
 
     loop {
-        match syscall!(connect(sock, &socket_addr)) {
+        match syscall!(connect(sock, socket_addr)) {
             Ok(_) => break,
             Err(Error::Nix(nix::Error::Sys(Errno::EINPROGRESS), _)) => continue,
-            Err(e) => return Err(e),
+            Err(e) => {
+                let _ = close_socket(sock);
+                return Err(e);
+            }
         }
     }
 
@@ -114,6 +208,8 @@ macro_rules! handle_timeout {
                 $start = std::time::Instant::now();
                 Ok(amt)
             }
+            // Interrupted by a signal before anything was transferred, just try again
+            Err(Error::Nix(nix::Error::Sys(Errno::EINTR), _)) => continue,
             Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), s)) => {
                 if $start.elapsed() > $timeout {
                     if ($has_data) {
@@ -132,15 +228,30 @@ macro_rules! handle_timeout {
 }
 
 pub fn receive_message(sock: Socket) -> Result<Option<Message>, Error> {
-    let message_header = read_meta(sock)?;
+    return receive_message_limited(sock, DEFAULT_MAX_MESSAGE_LENGTH);
+}
+
+/// Receives a message no longer than `max_length` bytes. If the header says the message is longer
+/// than that, nothing more is read and `Error::TooLarge` is returned. The rest of the message is
+/// still waiting on the socket then, so the connection is out of sync and should be closed.
+pub fn receive_message_limited(sock: Socket, max_length: usize) -> Result<Option<Message>, Error> {
+    let message_header = match read_meta(sock)? {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+    if message_header.message_length < 0 || message_header.message_length as u64 > max_length as u64
+    {
+        return Err(Error::TooLarge(message_header.message_length, max_length));
+    }
     let message_length = message_header.message_length as usize;
 
     let timeout = Duration::from_millis(TIMEOUT_MILLIS);
     let mut start = Instant::now();
 
-    let mut message_buffer: [u8; MESSAGE_SIZE] = [0; MESSAGE_SIZE];
+    // Too large to keep on the stack, and most messages are far smaller than this anyways
+    let mut message_buffer = vec![0u8; min(MESSAGE_SIZE, message_length)];
 
-    let mut output_buffer = Vec::<u8>::new();
+    let mut output_buffer = Vec::<u8>::with_capacity(message_buffer.len());
 
     let mut total_received: usize = 0;
     while total_received < message_length {
@@ -174,7 +285,7 @@ pub fn send_message(sock: Socket, message: &Message) -> Result<(), Error> {
 
     let message_data = message.message_text.as_bytes();
 
-    let timeout = Duration::from_millis(TIMEOUT_MILLIS);
+    let timeout = Duration::from_millis(SEND_TIMEOUT_MILLIS);
     let mut start = Instant::now();
 
     let mut total_sent: usize = 0;
@@ -192,13 +303,43 @@ pub fn send_message(sock: Socket, message: &Message) -> Result<(), Error> {
 pub fn accept_connection(sock: Socket) -> Result<Option<Socket>, Error> {
     let res = syscall!(accept(sock));
     return match res {
+        Ok(client_sock) if is_foreign_abstract_peer(sock, client_sock) => {
+            // Treated the same as nobody having connected yet
+            let _ = close_socket(client_sock);
+            Ok(None)
+        }
         Ok(client_sock) => Ok(Some(client_sock)),
         Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), _)) => Ok(None),
         Err(e) => Err(e),
     };
 }
 
-fn read_meta(sock: Socket) -> Result<MessageHeader, Error> {
+/// Whether a client connected to an abstract socket as a user other than the one the server runs
+/// as. Socket files leave that up to their permissions, abstract sockets have none, so anyone on
+/// the machine could connect otherwise.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_foreign_abstract_peer(sock: Socket, client_sock: Socket) -> bool {
+    let is_abstract = match getsockname(sock) {
+        Ok(SockAddr::Unix(addr)) => addr.as_abstract().is_some(),
+        _ => false,
+    };
+    if !is_abstract {
+        return false;
+    }
+
+    return match getsockopt(client_sock, PeerCredentials) {
+        Ok(cred) => cred.uid() != getuid().as_raw(),
+        Err(_) => true,
+    };
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn is_foreign_abstract_peer(_sock: Socket, _client_sock: Socket) -> bool {
+    return false;
+}
+
+/// Returns `None` if the socket was closed before the header could be read.
+fn read_meta(sock: Socket) -> Result<Option<MessageHeader>, Error> {
     // meta_buffer will contain:
     //  * message_type (first 8 bytes)
     //  * message_length (last 8 bytes)
@@ -208,13 +349,19 @@ fn read_meta(sock: Socket) -> Result<MessageHeader, Error> {
     // Both numbers are big endian
     let mut meta_buffer: [u8; META_SIZE] = [0; META_SIZE];
 
-    let message_type = read_i64(sock, &mut meta_buffer, true)?;
-    let message_length = read_i64(sock, &mut meta_buffer, false)?;
+    let message_type = match read_i64(sock, &mut meta_buffer, true)? {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let message_length = match read_i64(sock, &mut meta_buffer, false)? {
+        Some(l) => l,
+        None => return Ok(None),
+    };
 
-    return Ok(MessageHeader {
+    return Ok(Some(MessageHeader {
         message_type,
         message_length,
-    });
+    }));
 }
 
 fn send_meta(sock: Socket, message_header: &MessageHeader) -> Result<(), Error> {
@@ -224,7 +371,11 @@ fn send_meta(sock: Socket, message_header: &MessageHeader) -> Result<(), Error>
     return Ok(());
 }
 
-fn read_i64(sock: Socket, buffer: &mut [u8; META_SIZE], is_start: bool) -> Result<i64, Error> {
+fn read_i64(
+    sock: Socket,
+    buffer: &mut [u8; META_SIZE],
+    is_start: bool,
+) -> Result<Option<i64>, Error> {
     let timeout = Duration::from_millis(TIMEOUT_MILLIS);
     let mut start = Instant::now();
 
@@ -233,16 +384,20 @@ fn read_i64(sock: Socket, buffer: &mut [u8; META_SIZE], is_start: bool) -> Resul
         let res = syscall!(recv(sock, &mut buffer[total_received..], MsgFlags::empty()));
         let amount_received =
             handle_timeout!(res, timeout, start, !is_start || total_received > 0)?;
+        if amount_received == 0 {
+            // The other side closed the socket
+            return Ok(None);
+        }
         total_received += amount_received;
     }
 
-    return Ok(i64::from_be_bytes(*buffer));
+    return Ok(Some(i64::from_be_bytes(*buffer)));
 }
 
 fn write_i64(sock: Socket, value: i64, is_start: bool) -> Result<(), Error> {
     let buffer: [u8; META_SIZE] = value.to_be_bytes();
 
-    let timeout = Duration::from_millis(TIMEOUT_MILLIS);
+    let timeout = Duration::from_millis(SEND_TIMEOUT_MILLIS);
     let mut start = Instant::now();
 
     let mut total_sent: usize = 0;
@@ -258,6 +413,8 @@ fn write_i64(sock: Socket, value: i64, is_start: bool) -> Result<(), Error> {
 pub enum Error {
     Nix(nix::Error, Option<String>),
     Internal(String),
+    /// The length of a message being received, and the largest length which was accepted
+    TooLarge(i64, usize),
 }
 
 impl Error {
@@ -269,6 +426,7 @@ impl Error {
         return match &self {
             Error::Nix(e, _) => Error::Nix(e.clone(), Some(syscall.to_string())),
             Error::Internal(s) => Error::Internal(s.clone()),
+            Error::TooLarge(length, max) => Error::TooLarge(*length, *max),
         };
     }
 }
@@ -282,6 +440,11 @@ impl Display for Error {
             }
             Error::Nix(e, None) => e.fmt(f),
             Error::Internal(s) => write!(f, "{}", s),
+            Error::TooLarge(length, max) => write!(
+                f,
+                "Message too large: {} bytes, the limit is {} bytes",
+                length, max
+            ),
         };
     }
 }
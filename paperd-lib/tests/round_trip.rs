@@ -0,0 +1,338 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Round trips messages through a fake server listening on a real Unix socket, to make sure the
+//! framing of messages survives being split over many `send` and `recv` calls. Binding the socket
+//! file is checked here as well, along with abstract sockets.
+
+use nix::errno::Errno;
+use paperd_lib::{
+    abstract_socket_name, accept_connection, bind_socket, close_socket, connect_socket,
+    create_socket, receive_message, receive_message_limited, send_message, Error, Message,
+    MessageHeader, Socket, DEFAULT_MAX_MESSAGE_LENGTH, DEFAULT_SOCKET_MODE,
+};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use paperd_lib::{bind_abstract_socket, connect_abstract_socket};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::thread::JoinHandle;
+
+static SOCKET_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn check<T>(res: Result<T, Error>) -> T {
+    return match res {
+        Ok(t) => t,
+        Err(e) => panic!("{}", e),
+    };
+}
+
+fn sock_path() -> PathBuf {
+    let count = SOCKET_COUNT.fetch_add(1, Ordering::SeqCst);
+    return env::temp_dir().join(format!("paperd-test-{}-{}.sock", std::process::id(), count));
+}
+
+/// Waits for the next message, the sockets have a short receive timeout which shows up as `EAGAIN`.
+fn receive(sock: Socket) -> Option<Message> {
+    loop {
+        match receive_message(sock) {
+            Ok(m) => return m,
+            Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), _)) => continue,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+fn message(message_type: i64, text: &str) -> Message {
+    return Message {
+        header: MessageHeader {
+            message_type,
+            message_length: text.len() as i64,
+        },
+        message_text: text.to_string(),
+    };
+}
+
+/// Starts a fake server which answers each message on a single connection with `respond`, until
+/// the client closes the connection. The socket file is bound before this returns, so clients can
+/// connect right away.
+fn fake_server<F>(path: &PathBuf, respond: F) -> JoinHandle<()>
+where
+    F: Fn(&Message) -> Message + Send + 'static,
+{
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+
+    return thread::spawn(move || {
+        let client = loop {
+            if let Some(s) = check(accept_connection(server_sock)) {
+                break s;
+            }
+        };
+
+        while let Some(msg) = receive(client) {
+            check(send_message(client, &respond(&msg)));
+        }
+
+        check(close_socket(client));
+        check(close_socket(server_sock));
+    });
+}
+
+fn round_trip(text: &str) {
+    let path = sock_path();
+    let server = fake_server(&path, |msg| {
+        return message(
+            msg.header.message_type + 1,
+            format!("{{\"echo\":{}}}", msg.message_text).as_str(),
+        );
+    });
+
+    let sock = check(connect_socket(&path));
+    check(send_message(sock, &message(4, text)));
+    let response = receive(sock).expect("server closed the socket without responding");
+    check(close_socket(sock));
+
+    server.join().unwrap();
+    let _ = fs::remove_file(&path);
+
+    let expected = format!("{{\"echo\":{}}}", text);
+    assert_eq!(response.header.message_type, 5);
+    assert_eq!(response.header.message_length, expected.len() as i64);
+    assert_eq!(response.message_text, expected);
+}
+
+#[test]
+fn small_message() {
+    round_trip("{\"message\":\"say hello\"}");
+}
+
+#[test]
+fn empty_message() {
+    round_trip("{}");
+}
+
+#[test]
+fn multi_page_message() {
+    // Much larger than a single page, with multi-byte characters which will end up split across
+    // page boundaries
+    let players: Vec<String> = (0..20_000)
+        .map(|i| format!("\"player-{}-\u{00e9}\u{1f600}\"", i))
+        .collect();
+    let text = format!("{{\"players\":[{}]}}", players.join(","));
+    assert!(text.len() > 256 * 1024);
+
+    round_trip(text.as_str());
+}
+
+#[test]
+fn several_messages_on_one_connection() {
+    let path = sock_path();
+    let server = fake_server(&path, |msg| {
+        return message(msg.header.message_type, msg.message_text.as_str());
+    });
+
+    let sock = check(connect_socket(&path));
+    for i in 0..10 {
+        let text = format!("{{\"count\":{}}}", "1".repeat(i * 10_000 + 1));
+        check(send_message(sock, &message(i as i64, text.as_str())));
+
+        let response = receive(sock).expect("server closed the socket without responding");
+        assert_eq!(response.header.message_type, i as i64);
+        assert_eq!(response.message_text, text);
+    }
+    check(close_socket(sock));
+
+    server.join().unwrap();
+    let _ = fs::remove_file(&path);
+}
+
+/// Sends a message with a header claiming `length` bytes, and checks it's refused without reading
+/// the rest of it.
+fn too_large(length: i64, max_length: usize) {
+    let path = sock_path();
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+
+    // Not a fake_server, the client hangs up without reading the rest of the response, so the
+    // server mustn't expect anything from it
+    let server = thread::spawn(move || {
+        let client = loop {
+            if let Some(s) = check(accept_connection(server_sock)) {
+                break s;
+            }
+        };
+        let response = Message {
+            header: MessageHeader {
+                message_type: 1,
+                message_length: length,
+            },
+            message_text: "{}".to_string(),
+        };
+        // The client may already have hung up by the time the body is sent
+        let _ = send_message(client, &response);
+        check(close_socket(client));
+        check(close_socket(server_sock));
+    });
+
+    let sock = check(connect_socket(&path));
+    let res = loop {
+        match receive_message_limited(sock, max_length) {
+            Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), _)) => continue,
+            res => break res,
+        }
+    };
+    check(close_socket(sock));
+
+    server.join().unwrap();
+    let _ = fs::remove_file(&path);
+
+    match res {
+        Err(Error::TooLarge(l, max)) => {
+            assert_eq!(l, length);
+            assert_eq!(max, max_length);
+        }
+        Err(e) => panic!("{}", e),
+        Ok(_) => panic!("Received a message larger than the limit"),
+    }
+}
+
+#[test]
+fn message_too_large() {
+    too_large(
+        DEFAULT_MAX_MESSAGE_LENGTH as i64 + 1,
+        DEFAULT_MAX_MESSAGE_LENGTH,
+    );
+    too_large(1024, 100);
+}
+
+#[test]
+fn negative_message_length() {
+    too_large(-1, DEFAULT_MAX_MESSAGE_LENGTH);
+}
+
+#[test]
+fn closed_connection() {
+    let path = sock_path();
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+
+    let server = thread::spawn(move || {
+        let client = loop {
+            if let Some(s) = check(accept_connection(server_sock)) {
+                break s;
+            }
+        };
+        // Hang up without saying anything
+        check(close_socket(client));
+        check(close_socket(server_sock));
+    });
+
+    let sock = check(connect_socket(&path));
+    server.join().unwrap();
+
+    assert!(receive(sock).is_none());
+    check(close_socket(sock));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn bind_socket_in_use() {
+    let path = sock_path();
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+
+    let other_sock = check(create_socket());
+    match bind_socket(other_sock, path.to_str().unwrap()) {
+        Err(Error::Nix(nix::Error::Sys(Errno::EADDRINUSE), _)) => {}
+        Err(e) => panic!("{}", e),
+        Ok(()) => panic!("Bound a socket file which is in use"),
+    }
+
+    // The first server must still be reachable
+    let sock = check(connect_socket(&path));
+    check(close_socket(sock));
+
+    check(close_socket(other_sock));
+    check(close_socket(server_sock));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn bind_stale_socket() {
+    let path = sock_path();
+    let old_sock = check(create_socket());
+    check(bind_socket(old_sock, path.to_str().unwrap()));
+    // Closing the socket leaves the socket file behind, like a crashed server
+    check(close_socket(old_sock));
+    assert!(path.exists());
+
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+
+    let sock = check(connect_socket(&path));
+    check(close_socket(sock));
+    check(close_socket(server_sock));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn bind_socket_mode() {
+    let mode_of = |path: &PathBuf| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+    let path = sock_path();
+    let server_sock = check(create_socket());
+    check(bind_socket(server_sock, path.to_str().unwrap()));
+    // Not whatever the umask happens to allow
+    assert_eq!(mode_of(&path), DEFAULT_SOCKET_MODE);
+    check(close_socket(server_sock));
+    let _ = fs::remove_file(&path);
+
+    let server_sock = check(create_socket());
+    check(paperd_lib::bind_socket_mode(
+        server_sock,
+        path.to_str().unwrap(),
+        0o660,
+    ));
+    assert_eq!(mode_of(&path), 0o660);
+    check(close_socket(server_sock));
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_socket_survives_file_removal() {
+    let path = sock_path();
+    let name = abstract_socket_name(path.to_str().unwrap().as_bytes());
+    let server_sock = check(create_socket());
+    check(bind_abstract_socket(server_sock, &name));
+
+    // Nothing is created in the filesystem, so there's nothing to delete or replace
+    assert!(!path.exists());
+
+    let sock = check(connect_abstract_socket(&name));
+    check(close_socket(sock));
+    check(close_socket(server_sock));
+}
+
+#[test]
+fn abstract_socket_name_is_stable() {
+    // Clients and servers built separately have to agree on the name
+    assert_eq!(abstract_socket_name(b""), "paperd-cbf29ce484222325");
+    assert_eq!(abstract_socket_name(b"a"), "paperd-af63dc4c8601ec8c");
+}
@@ -0,0 +1,110 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::log::{find_log_file, spawn_tail};
+use crate::protocol::check_protocol;
+use crate::send::send_command;
+use crate::util::{get_sock, ExitError, ExitValue};
+use clap::ArgMatches;
+use crossbeam_channel::{unbounded, Sender};
+use signal_hook::iterator::Signals;
+use signal_hook::SIGINT;
+use std::io;
+use std::io::BufRead;
+use std::thread;
+
+// How many lines of the log to show when first attaching
+const INITIAL_LINES: usize = 20;
+
+enum Input {
+    Line(String),
+    Detach,
+}
+
+/// A line based alternative to the full console. Output from the server's log is printed to stdout
+/// as it's written, and each line read from stdin is sent to the server as a command. The session
+/// ends on C-c or at the end of stdin, the server keeps running either way.
+pub fn attach(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let (sock, sock_file) = get_sock(sub_m)?;
+    check_protocol(&sock)?;
+
+    let log_file = find_log_file(sub_m, &sock_file)?;
+
+    // Register this before anything else is started, so C-c can't kill paperd itself
+    let signals = Signals::new(&[SIGINT]).conv("Failed to register signal handlers")?;
+
+    let mut tail_child = match spawn_tail(&log_file, INITIAL_LINES, true) {
+        Ok(c) => c,
+        Err(e) => {
+            signals.close();
+            return Err(e);
+        }
+    };
+
+    let (sender, receiver) = unbounded::<Input>();
+
+    let signals_bg = signals.clone();
+    let signal_sender = sender.clone();
+    thread::spawn(move || {
+        if signals_bg.forever().next().is_some() {
+            let _ = signal_sender.send(Input::Detach);
+        }
+    });
+
+    thread::spawn(move || read_input(sender));
+
+    let mut result = Ok(());
+    for input in receiver.iter() {
+        let line = match input {
+            Input::Line(line) => line,
+            Input::Detach => break,
+        };
+
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = send_command(&sock, command) {
+            if let ExitValue::Shutdown = e {
+                eprintln!("The server is shutting down, detaching");
+            }
+            result = Err(e);
+            break;
+        }
+    }
+
+    signals.close();
+    // tail has already exited if C-c was pressed, this only matters at the end of stdin
+    let _ = tail_child.kill();
+    let _ = tail_child.wait();
+
+    return result;
+}
+
+fn read_input(sender: Sender<Input>) {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        match line {
+            Ok(line) => {
+                if sender.send(Input::Line(line)).is_err() {
+                    return;
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    let _ = sender.send(Input::Detach);
+}
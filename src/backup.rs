@@ -0,0 +1,203 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::client::Client;
+use crate::exec::{exec_command, DEFAULT_EXEC_TIMEOUT};
+use crate::info;
+use crate::runner::local_timestamp;
+use crate::send::UNREACHABLE_EXIT_CODE;
+use crate::util::{find_program, get_sock, server_working_dir, ExitError, ExitValue};
+use crate::verbose;
+use clap::ArgMatches;
+use signal_hook::iterator::Signals;
+use signal_hook::{SIGINT, SIGTERM};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+// Where backups go when no --out is given, relative to the server's working directory
+const BACKUP_DIR_NAME: &str = "backups";
+
+/// Makes a consistent backup of the server's worlds while it keeps running. Saving is turned off
+/// so the world files don't change while they're being archived, then everything is saved and the
+/// archive is made, and saving is turned back on afterwards no matter how the archive step went.
+pub fn backup(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let timeout = Duration::from_secs(match sub_m.value_of("TIMEOUT") {
+        Some(t) => t.parse::<u64>().conv("Failed to parse --timeout")?,
+        None => DEFAULT_EXEC_TIMEOUT,
+    });
+
+    let (sock, sock_file) = get_sock(sub_m).map_err(|_| ExitValue::Code(UNREACHABLE_EXIT_CODE))?;
+    let client = Client::from_socket(sock, &sock_file)?;
+
+    let working_dir = server_working_dir(&sock_file);
+
+    // The worlds to archive are only known while the server is reachable, ask before anything is
+    // changed
+    let archive = match sub_m.value_of("COMMAND") {
+        Some(command) => Archive::Command(command.to_string()),
+        None => {
+            let status = client.status()?;
+            let worlds = status
+                .world_names()
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<String>>();
+            let out = match sub_m.value_of("OUT") {
+                Some(out) => PathBuf::from(out),
+                None => default_backup_file(&working_dir),
+            };
+            Archive::Tar { worlds, out }
+        }
+    };
+
+    // Stopping paperd partway through would leave saving turned off, so C-c and SIGTERM are only
+    // passed on to the archive command, which shares our process group, and the backup fails
+    let signals = Signals::new(&[SIGINT, SIGTERM]).conv("Failed to register signal handlers")?;
+
+    info!("Turning off saving");
+    exec_command(&client, "save-off", timeout)?;
+    let guard = SaveOnGuard {
+        client: &client,
+        sock_file: &sock_file,
+        timeout,
+    };
+
+    info!("Saving the worlds");
+    exec_command(&client, "save-all flush", timeout)?;
+
+    let result = archive.run(&working_dir);
+
+    drop(guard);
+    signals.close();
+    return result;
+}
+
+/// How the worlds are archived once they're saved.
+enum Archive {
+    /// A shell command given with --command, run in the server's working directory
+    Command(String),
+    /// A gzipped tarball of the world directories, made with the system's `tar`
+    Tar { worlds: Vec<String>, out: PathBuf },
+}
+
+impl Archive {
+    fn run(&self, working_dir: &Path) -> Result<(), ExitValue> {
+        return match self {
+            Archive::Command(command) => {
+                info!("Running the backup command");
+                let status = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .current_dir(working_dir)
+                    .status()
+                    .conv("Failed to run the backup command")?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    eprintln!("The backup command failed: {}", status);
+                    Err(ExitValue::Code(1))
+                }
+            }
+            Archive::Tar { worlds, out } => archive_worlds(working_dir, worlds, out),
+        };
+    }
+}
+
+fn archive_worlds(working_dir: &Path, worlds: &[String], out: &Path) -> Result<(), ExitValue> {
+    if worlds.is_empty() {
+        eprintln!("The server has no worlds loaded, there's nothing to back up");
+        return Err(ExitValue::Code(1));
+    }
+
+    let tar_prog = match find_program(&[("PATH", "tar")]) {
+        Some(t) => t,
+        None => {
+            eprintln!("Failed to find 'tar' program on the PATH");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    if let Some(dir) = out.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).conv(format!("Failed to create {}", dir.display()))?;
+    }
+    // tar runs in the working directory, relative paths given on the command line are relative to
+    // the directory paperd was run from
+    let out = match fs::canonicalize(out.parent().unwrap_or_else(|| Path::new("."))) {
+        Ok(dir) => dir.join(out.file_name().unwrap_or_default()),
+        Err(_) => out.to_path_buf(),
+    };
+
+    info!("Archiving {} to {}", worlds.join(", "), out.display());
+    verbose!(
+        "Running {} in {}",
+        tar_prog.display(),
+        working_dir.display()
+    );
+    let status = Command::new(&tar_prog)
+        .arg("-czf")
+        .arg(&out)
+        .arg("--")
+        .args(worlds)
+        .current_dir(working_dir)
+        .status()
+        .conv("Failed to run tar")?;
+
+    if !status.success() {
+        eprintln!("tar failed: {}", status);
+        // Don't leave a partial archive around which looks like a good backup
+        let _ = fs::remove_file(&out);
+        return Err(ExitValue::Code(1));
+    }
+
+    return Ok(());
+}
+
+/// `backups/2020-05-17_14-30-00.tar.gz` in the server's working directory.
+fn default_backup_file(working_dir: &Path) -> PathBuf {
+    let name = local_timestamp().replace(' ', "_").replace(':', "-");
+    return working_dir
+        .join(BACKUP_DIR_NAME)
+        .join(format!("{}.tar.gz", name));
+}
+
+/// Turns saving back on when dropped, whether or not the backup succeeded.
+struct SaveOnGuard<'a> {
+    client: &'a Client,
+    sock_file: &'a Path,
+    timeout: Duration,
+}
+
+impl Drop for SaveOnGuard<'_> {
+    fn drop(&mut self) {
+        info!("Turning saving back on");
+        if exec_command(self.client, "save-on", self.timeout).is_ok() {
+            return;
+        }
+
+        // The archive step may have taken long enough for the connection to be dropped, try once
+        // more with a new one
+        let retried = Client::connect(self.sock_file)
+            .map_err(ExitValue::from)
+            .and_then(|client| exec_command(&client, "save-on", self.timeout));
+        if retried.is_err() {
+            eprintln!(
+                "Failed to turn saving back on, the server is NOT saving the worlds. Run \
+                 `paperd exec save-on` to turn it back on."
+            );
+        }
+    }
+}
@@ -0,0 +1,78 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::info;
+use crate::runner::{LAUNCH_FILE_NAME, PID_FILE_NAME, SOCK_FILE_NAME};
+use crate::util::{
+    get_sock_from_file_direct, server_state, sock_file_path, ExitError, ExitValue, ServerState,
+};
+use clap::ArgMatches;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn cleanup(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let (pid_file, sock_file) = match sub_m.value_of("PID_FILE") {
+        Some(pid_file) => {
+            let pid_file = PathBuf::from(pid_file);
+            let sock_file = sibling(&pid_file, SOCK_FILE_NAME);
+            (pid_file, sock_file)
+        }
+        None => {
+            let sock_file = sock_file_path(sub_m)?;
+            (sibling(&sock_file, PID_FILE_NAME), sock_file)
+        }
+    };
+    let launch_file = sibling(&pid_file, LAUNCH_FILE_NAME);
+
+    if let ServerState::Running { pid } = server_state(&pid_file) {
+        eprintln!(
+            "The server is still running with PID {}, refusing to clean up. Use `paperd stop` to \
+             stop it first.",
+            pid
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    // Servers started with `paperd run` don't have a PID file, so check the socket itself as well
+    if get_sock_from_file_direct(&sock_file).is_ok() {
+        eprintln!(
+            "A server is still accepting connections on socket {}, refusing to clean up.",
+            sock_file.display()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    let mut removed = false;
+    for file in &[&pid_file, &sock_file, &launch_file] {
+        if file.exists() {
+            fs::remove_file(file).conv(format!("Failed to delete {}", file.display()))?;
+            info!("Removed {}", file.display());
+            removed = true;
+        }
+    }
+
+    if !removed {
+        info!("Nothing to clean up");
+    }
+
+    return Ok(());
+}
+
+fn sibling(file: &Path, name: &str) -> PathBuf {
+    return match file.parent() {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    };
+}
@@ -0,0 +1,245 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::exec::{ExecCommandMessage, ExecCommandMessageResponse};
+use crate::messaging::{MessageError, MessageSocket};
+use crate::ping::{PingMessage, PingMessageResponse};
+use crate::protocol::request_protocol;
+use crate::runner::PID_FILE_NAME;
+use crate::send::{CaptureCommandMessage, CaptureCommandMessageResponse, SendCommandMessage};
+use crate::status::{StatusMessage, StatusMessageResponse};
+use crate::stop::StopMessage;
+use crate::util::{get_sock_from_file_direct, ExitValue};
+use crate::version::{ServerVersionMessage, ServerVersionMessageResponse};
+use nix::sys::signal::{kill, SIGKILL};
+use nix::unistd::Pid;
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub use crate::protocol::PROTOCOL_VERSION;
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A connection to a running Paper server. Unlike the commands, nothing is printed here, every
+/// failure is returned as a `PaperError` for the caller to handle or report.
+pub struct Client {
+    sock: MessageSocket,
+    sock_file: PathBuf,
+}
+
+pub enum PaperError {
+    /// The socket file couldn't be connected to
+    Connect(paperd_lib::Error),
+    /// The server uses a different protocol version than paperd does
+    Protocol { server: i64, paperd: i64 },
+    /// Communicating with the server failed
+    Message(MessageError),
+    /// The server wasn't started by paperd, so there's no PID file to find its process with
+    NoPidFile(PathBuf),
+    /// The server's PID file couldn't be read
+    PidFile(PathBuf, String),
+    /// The server's process couldn't be killed
+    Kill(nix::Error),
+    /// The server responded to a ping with a different payload than it was sent
+    PingMismatch,
+}
+
+impl Client {
+    /// Connects to the server listening on `sock_file` and checks it speaks the same protocol
+    /// version as paperd.
+    pub fn connect<P: AsRef<Path>>(sock_file: P) -> Result<Client, PaperError> {
+        let sock = get_sock_from_file_direct(sock_file.as_ref()).map_err(PaperError::Connect)?;
+        return Client::from_socket(sock, sock_file);
+    }
+
+    /// Same as `connect`, but for a socket which has already been connected.
+    pub fn from_socket<P: AsRef<Path>>(
+        sock: MessageSocket,
+        sock_file: P,
+    ) -> Result<Client, PaperError> {
+        request_protocol(&sock)?;
+
+        return Ok(Client {
+            sock,
+            sock_file: sock_file.as_ref().to_path_buf(),
+        });
+    }
+
+    pub fn socket(&self) -> &MessageSocket {
+        return &self.sock;
+    }
+
+    pub fn status(&self) -> Result<StatusMessageResponse, PaperError> {
+        self.sock.send_message(&StatusMessage {})?;
+
+        return Ok(self.sock.receive_message_timeout(RESPONSE_TIMEOUT)?);
+    }
+
+    /// Measures the time it takes for a message to make it to the server and back.
+    pub fn ping(&self) -> Result<Duration, PaperError> {
+        // Make sure the response is to this ping, not something left over from earlier
+        let payload = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        let start = Instant::now();
+        self.sock.send_message(&PingMessage { payload })?;
+        let res = self
+            .sock
+            .receive_message_timeout::<PingMessageResponse>(RESPONSE_TIMEOUT)?;
+        let time = start.elapsed();
+
+        if res.payload != payload {
+            return Err(PaperError::PingMismatch);
+        }
+
+        return Ok(time);
+    }
+
+    /// Asks the server which Minecraft version and Paper build it's running.
+    pub fn server_version(&self) -> Result<ServerVersionMessageResponse, PaperError> {
+        self.sock.send_message(&ServerVersionMessage {})?;
+
+        return Ok(self.sock.receive_message_timeout(RESPONSE_TIMEOUT)?);
+    }
+
+    pub fn send_command(&self, command: &str) -> Result<(), PaperError> {
+        self.sock.send_message(&SendCommandMessage::new(command))?;
+
+        return Ok(());
+    }
+
+    /// Runs the command and returns the console output it produced. The server collects the output
+    /// while the command runs, `timeout` is how long to wait for it to respond.
+    pub fn capture_command(
+        &self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<Vec<String>, PaperError> {
+        self.sock.send_message(&CaptureCommandMessage {
+            message: command.to_string(),
+        })?;
+        let res = self
+            .sock
+            .receive_message_timeout::<CaptureCommandMessageResponse>(timeout)?;
+
+        return Ok(res.lines);
+    }
+
+    /// Runs the command and waits for the server to confirm it has finished running, along with
+    /// whether it succeeded. `timeout` is how long to wait for the confirmation.
+    pub fn exec_command(
+        &self,
+        command: &str,
+        timeout: Duration,
+    ) -> Result<ExecCommandMessageResponse, PaperError> {
+        self.sock.send_message(&ExecCommandMessage {
+            message: command.to_string(),
+        })?;
+
+        return Ok(self.sock.receive_message_timeout(timeout)?);
+    }
+
+    /// Stops the server. A graceful stop asks the server to shut down and returns without waiting
+    /// for it to exit. Otherwise the server's process is killed immediately, which is only possible
+    /// for servers started by `paperd start`, as the process is found through its PID file.
+    pub fn stop(mut self, graceful: bool) -> Result<(), PaperError> {
+        if graceful {
+            self.sock.send_message(&StopMessage {})?;
+            // Nothing else will be sent, so don't hold the connection open while the server shuts
+            // down
+            let _ = self.sock.close();
+            return Ok(());
+        }
+
+        let pid_file = match self.sock_file.parent() {
+            Some(dir) => dir.join(PID_FILE_NAME),
+            None => PathBuf::from(PID_FILE_NAME),
+        };
+        if !pid_file.is_file() {
+            return Err(PaperError::NoPidFile(pid_file));
+        }
+
+        let pid = fs::read_to_string(&pid_file)
+            .map_err(|e| e.to_string())
+            .and_then(|text| text.trim().parse::<i32>().map_err(|e| e.to_string()))
+            .map_err(|e| PaperError::PidFile(pid_file.clone(), e))?;
+
+        kill(Pid::from_raw(pid), SIGKILL).map_err(PaperError::Kill)?;
+
+        let _ = self.sock.close();
+        let _ = fs::remove_file(&self.sock_file);
+        let _ = fs::remove_file(&pid_file);
+
+        return Ok(());
+    }
+}
+
+impl From<MessageError> for PaperError {
+    fn from(e: MessageError) -> Self {
+        return PaperError::Message(e);
+    }
+}
+
+impl Display for PaperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            PaperError::Connect(e) => write!(
+                f,
+                "Failed to connect to the Paper server\n  Caused by: Error during system call: {}",
+                e
+            ),
+            PaperError::Protocol { server, paperd } => write!(
+                f,
+                "The protocol versions of paperd and the specified server do not match. \
+                 paperd protocol version: {}; server protocol version: {}. Please use a version \
+                 of paperd compatible with this build of Paper.",
+                paperd, server
+            ),
+            PaperError::Message(e) => write!(f, "{}", e),
+            PaperError::NoPidFile(file) => write!(
+                f,
+                "No PID file found at {}, only servers started with `paperd start` can be killed",
+                file.display()
+            ),
+            PaperError::PidFile(file, e) => {
+                write!(f, "Failed to read PID file {}: {}", file.display(), e)
+            }
+            PaperError::Kill(e) => write!(f, "Failed to kill the server: {}", e),
+            PaperError::PingMismatch => {
+                write!(
+                    f,
+                    "The Paper server responded to the ping with the wrong payload"
+                )
+            }
+        };
+    }
+}
+
+impl From<PaperError> for ExitValue {
+    fn from(e: PaperError) -> Self {
+        return match e {
+            PaperError::Message(e) => ExitValue::from(e),
+            e => {
+                eprintln!("{}", e);
+                ExitValue::Code(1)
+            }
+        };
+    }
+}
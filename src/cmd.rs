@@ -13,31 +13,70 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::util::ExitValue;
 use clap::{App, AppSettings, Arg, ArgGroup, ArgMatches, Shell, SubCommand};
 use std::io;
 
+pub const JVM_ARGS_CONFLICT: &str = "Aikar's flags (-d or --default-args) can't be combined with \
+     custom JVM arguments. Either use --default-args to set the amount of memory and use the \
+     recommended flags, or pass all of the JVM arguments yourself after --. See the EXAMPLES \
+     section of --help for how to use each.";
+
+pub const ARGS_FILE_CONFLICT: &str = "JVM arguments from a file (--args-file) can't be combined \
+     with Aikar's flags (-d or --default-args) or with JVM arguments passed after --. Either put \
+     all of the JVM arguments in the file or pass them all on the command line.";
+
+pub const GC_CONFLICT: &str = "--gc chooses which default JVM arguments are used, so it can't be \
+     combined with custom JVM arguments or --args-file. Either use --gc with --default-args, or \
+     pass the flags for the garbage collector yourself.";
+
+pub const JVM_ARG_CONFLICT: &str = "--jvm-arg adds JVM arguments on top of the default ones, so \
+     it can't be combined with custom JVM arguments or --args-file. Put the extra arguments with \
+     the others instead.";
+
+pub const DEFAULT_ARGS_PERCENT_CONFLICT: &str = "--default-args-percent sets the amount of memory \
+     for Aikar's flags from the system's memory instead of an absolute amount, so it can't be \
+     combined with -d or --default-args, with custom JVM arguments or with --args-file.";
+
 pub fn get_cmd_line_matches<'a>() -> ArgMatches<'a> {
     let start_text = run_after_text("start");
     let run_text = run_after_text("run");
     return handle_cmd_line(start_text.as_str(), run_text.as_str()).get_matches();
 }
 
-pub fn gen_completions(shell: &str) {
+pub fn completions(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let shell = match sub_m.value_of("SHELL").map(|s| s.parse::<Shell>()) {
+        Some(Ok(shell)) => shell,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            return Err(ExitValue::Code(1));
+        }
+        None => {
+            eprintln!("No shell provided");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    // The app is rebuilt here, as generating completions consumes it
     let start_text = run_after_text("start");
     let run_text = run_after_text("run");
     handle_cmd_line(start_text.as_str(), run_text.as_str()).gen_completions_to(
         "paperd",
-        shell.parse::<Shell>().unwrap(),
+        shell,
         &mut io::stdout(),
     );
+
+    return Ok(());
 }
 
 fn handle_cmd_line<'a, 'b>(start_after: &'b str, run_after: &'b str) -> App<'a, 'b> {
     let sock_arg = Arg::<'a, 'b>::with_name("SOCK")
         .help(
-            "Custom socket file to send commands to a running server. If not set, the \
-             PAPERD_SOCK environment variable will be checked. If neither are set, the default \
-             value is ./paper.sock.",
+            "Custom socket file to send commands to a running server. If not set, the socket \
+             of the instance given by --instance is used, then the PAPERD_SOCK environment \
+             variable will be checked. If none of these are set, paper.sock in the current \
+             directory is used, or when inside a server's directory, the server's paper.sock \
+             from the closest parent directory which has one.",
         )
         .short("s")
         .long("sock")
@@ -61,10 +100,142 @@ LICENSE:
         .version(crate_version!())
         .author("PaperMC (papermc.io)")
         .about("PaperMC daemon for running and controlling daemonized PaperMC servers.")
+        .arg(
+            Arg::with_name("QUIET")
+                .help(
+                    "Don't print progress or informational messages, only the output of the \
+                     command and errors.",
+                )
+                .short("q")
+                .long("quiet")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("VERBOSE")
+                .help(
+                    "Print extra details useful for debugging, such as which files are used and \
+                     the full command line the server is started with.",
+                )
+                .short("v")
+                .long("verbose")
+                .conflicts_with("QUIET")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("LOG_LEVEL")
+                .help(
+                    "How much paperd prints about what it's doing. quiet is the same as --quiet \
+                     and verbose the same as --verbose. debug also traces each step of talking \
+                     to the server, such as which socket and PID files are used and every \
+                     message sent and received, for when paperd itself misbehaves. Default is \
+                     normal.",
+                )
+                .long("log-level")
+                .value_name("LEVEL")
+                .takes_value(true)
+                .possible_values(&["quiet", "normal", "verbose", "debug"])
+                .conflicts_with_all(&["QUIET", "VERBOSE"])
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("COLOR")
+                .help(
+                    "When to color the output. auto colors it only when stdout is a terminal and \
+                     the NO_COLOR environment variable isn't set. Default is auto.",
+                )
+                .long("color")
+                .value_name("WHEN")
+                .takes_value(true)
+                .possible_values(&["auto", "always", "never"])
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("INSTANCE")
+                .help(
+                    "The name of the server instance to use. Starting a server with an instance \
+                     name registers the server's working directory under that name, other \
+                     commands then use that instance's server. This makes managing multiple \
+                     servers simpler, as you don't need to provide the socket file of each \
+                     server. An explicit --sock argument takes precedence over this.",
+                )
+                .short("i")
+                .long("instance")
+                .takes_value(true)
+                .global(true),
+        )
         .subcommand(
             SubCommand::with_name("status")
                 .about("Get the status of the currently running server.")
                 .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("JSON")
+                        .help(
+                            "Print the status as JSON rather than as a formatted report. This is \
+                             useful for scripts, the format is the same as the status response \
+                             described in the paperd protocol documentation.",
+                        )
+                        .short("j")
+                        .long("json"),
+                )
+                .arg(
+                    Arg::with_name("DEBUG")
+                        .help(
+                            "Before the status, print the connection to the server for \
+                             debugging: the full paths of the socket and PID files, the socket's \
+                             file descriptor, the process and user on the other end of the \
+                             socket, and the protocol version.",
+                        )
+                        .long("debug")
+                        .conflicts_with_all(&["JSON", "CHECK"]),
+                )
+                .arg(
+                    Arg::with_name("WATCH")
+                        .help(
+                            "Keep showing the status, refreshing it every INTERVAL seconds until \
+                             C-c is pressed. The default interval is 2 seconds. If the output \
+                             isn't a terminal the status is only shown once.",
+                        )
+                        .short("w")
+                        .long("watch")
+                        .value_name("INTERVAL")
+                        .takes_value(true)
+                        .min_values(0)
+                        .max_values(1)
+                        .conflicts_with("JSON"),
+                )
+                .arg(
+                    Arg::with_name("CHECK")
+                        .help(
+                            "Check the health of the server for monitoring tools. Nothing is \
+                             printed to stdout, the exit code is 0 if the server is healthy, 1 if \
+                             it can't be reached, and 2 if it's responding but degraded, for \
+                             example because its TPS is below --min-tps.",
+                        )
+                        .long("check")
+                        .conflicts_with_all(&["JSON", "WATCH"]),
+                )
+                .arg(
+                    Arg::with_name("MIN_TPS")
+                        .help(
+                            "The lowest TPS over the past minute which --check considers \
+                             healthy. If not set, the TPS isn't checked.",
+                        )
+                        .long("min-tps")
+                        .value_name("TPS")
+                        .takes_value(true)
+                        .requires("CHECK"),
+                )
+                .arg(
+                    Arg::with_name("PLAYERS")
+                        .help(
+                            "Only print the names of the players online, one per line. With \
+                             --json they are printed as a JSON array instead. If no players are \
+                             online nothing is printed, or an empty array with --json.",
+                        )
+                        .short("p")
+                        .long("players")
+                        .conflicts_with_all(&["WATCH", "CHECK"]),
+                )
                 .display_order(1)
                 .after_help(license_text),
         )
@@ -76,6 +247,7 @@ LICENSE:
                     "Tail the server log after sending the command to the \
                      server, useful for viewing the response. Press C-c to quit.",
                 ))
+                .arg(tail_lines_arg())
                 .arg(
                     Arg::with_name("COMMAND")
                         .help(
@@ -85,9 +257,168 @@ LICENSE:
                             arguments first.",
                         )
                         .multiple(true)
+                        .allow_hyphen_values(true),
+                )
+                .arg(
+                    Arg::with_name("STDIN")
+                        .help(
+                            "Read commands from stdin rather than the command line. Each \
+                             non-empty line is sent as a separate command.",
+                        )
+                        .long("stdin"),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help(
+                            "Read commands from a file rather than the command line. Each \
+                             non-empty line is sent as a separate command.",
+                        )
+                        .short("f")
+                        .long("file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("CAPTURE")
+                        .help(
+                            "Print the output of each command instead of only sending it. The \
+                             server collects the console output the command produces and sends \
+                             it back, so nothing else the server logs meanwhile is shown.",
+                        )
+                        .short("c")
+                        .long("capture")
+                        .conflicts_with("TAIL"),
+                )
+                .arg(
+                    Arg::with_name("CAPTURE_TIMEOUT")
+                        .help(
+                            "How many seconds to wait for the output of each command with \
+                             --capture. Default is 10.",
+                        )
+                        .long("capture-timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .requires("CAPTURE"),
+                )
+                .arg(
+                    Arg::with_name("RAW")
+                        .help(
+                            "Send commands containing control characters, such as newlines or \
+                             escape sequences, as they are. Without this they are refused, as \
+                             the server's console reads commands one line at a time and doesn't \
+                             expect them.",
+                        )
+                        .long("raw"),
+                )
+                .arg(log_file_arg().requires("TAIL"))
+                .group(
+                    ArgGroup::with_name("INPUT")
+                        .args(&["COMMAND", "STDIN", "FILE"])
+                        .required(true),
+                )
+                .after_help(
+                    "EXIT STATUS:\n    \
+                     0 if the command was sent, 2 if the server could not be reached, and 1 for \
+                     any other error. When sending multiple commands paperd stops at the first \
+                     command which fails to send.",
+                )
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about(
+                    "Run a command on the MC server and wait for it to finish. Unlike `send`, \
+                     this doesn't return until the server confirms the command has finished \
+                     running, so scripts can rely on e.g. `save-all` having completed.",
+                )
+                .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("COMMAND")
+                        .help(
+                            "The command to run on the MC server. Arguments will be appended \
+                             together as one string, with a single space between arguments.",
+                        )
+                        .multiple(true)
                         .allow_hyphen_values(true)
                         .required(true),
                 )
+                .arg(
+                    Arg::with_name("TIMEOUT")
+                        .help(
+                            "How many seconds to wait for the server to confirm the command has \
+                             finished. Default is 60.",
+                        )
+                        .short("t")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("RAW")
+                        .help(
+                            "Run commands containing control characters, such as newlines or \
+                             escape sequences, as they are. Without this they are refused.",
+                        )
+                        .long("raw"),
+                )
+                .after_help(
+                    "EXIT STATUS:\n    \
+                     0 if the command finished successfully, 1 if it failed or for any other \
+                     error, 2 if the server could not be reached, and 124 if the server didn't \
+                     confirm the command finished within --timeout. The command may still \
+                     finish after that.",
+                )
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("backup")
+                .about(
+                    "Back up the worlds of the running MC server. Saving is turned off with \
+                     `save-off` and the worlds are saved with `save-all flush`, so they don't \
+                     change while being archived. Saving is always turned back on with `save-on` \
+                     afterwards, even if the backup fails or is interrupted with C-c. By default \
+                     the world directories are archived with tar to \
+                     backups/<date>_<time>.tar.gz in the server's working directory.",
+                )
+                .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("OUT")
+                        .help("Write the archive of the worlds to this file instead.")
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("COMMAND")
+                        .help(
+                            "Run this shell command to make the backup instead of archiving the \
+                             worlds with tar, for example to take a filesystem snapshot. The \
+                             command is run in the working directory of the server, while saving \
+                             is turned off. The backup fails if the command exits with a non-zero \
+                             code.",
+                        )
+                        .short("c")
+                        .long("command")
+                        .value_name("COMMAND")
+                        .takes_value(true)
+                        .conflicts_with("OUT"),
+                )
+                .arg(
+                    Arg::with_name("TIMEOUT")
+                        .help(
+                            "How many seconds to wait for each of the save commands to finish. \
+                             Default is 60.",
+                        )
+                        .short("t")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true),
+                )
+                .after_help(
+                    "EXIT STATUS:\n    \
+                     0 if the backup was made, 2 if the server could not be reached, 124 if a \
+                     save command didn't finish within --timeout, and 1 for any other error.",
+                )
                 .display_order(1),
         )
         .console(&sock_arg)
@@ -102,25 +433,119 @@ LICENSE:
                         .long("lines")
                         .default_value("10"),
                 )
+                .arg(
+                    Arg::with_name("SINCE")
+                        .help(
+                            "Print the log messages from a recent window of time instead of a \
+                             number of lines, either a duration such as 10m or 2h, or a time of \
+                             day such as 14:30 or 14:30:00. Lines without a time, such as stack \
+                             traces, are printed along with the line before them.",
+                        )
+                        .long("since")
+                        .value_name("TIME")
+                        .takes_value(true)
+                        .conflicts_with("TAIL"),
+                )
+                .arg(
+                    Arg::with_name("INCLUDE_ROTATED")
+                        .help(
+                            "If the log file has fewer lines than --lines asks for, because it \
+                             was just rotated, read the rest from the gzipped logs the server \
+                             rotated out next to it, newest first.",
+                        )
+                        .long("include-rotated")
+                        .conflicts_with_all(&["SINCE", "TAIL"]),
+                )
+                .arg(
+                    Arg::with_name("LEVEL")
+                        .help(
+                            "Only print log messages at this level or above, such as WARN for \
+                             warnings and errors. Lines without a level, such as stack traces, \
+                             are printed along with the message they belong to. Works with \
+                             --tail to watch for problems as they happen. --lines then counts \
+                             only the lines which are printed.",
+                        )
+                        .long("level")
+                        .value_name("LEVEL")
+                        .takes_value(true)
+                        .possible_values(&["TRACE", "DEBUG", "INFO", "WARN", "ERROR", "FATAL"])
+                        .case_insensitive(true),
+                )
                 .arg(tail_arg(
                     "Tail the server log rather than just printing recent \
-                     messages. Press C-c.",
+                     messages. Following carries on across restarts, and stops once the server \
+                     has been gone for about a minute. Press C-c to quit.",
                 ))
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .help(
+                            "How to print log messages. json prints each line as an object with \
+                             the time, level, thread and message of the line, lines which can't \
+                             be split up that way, such as stack traces, are printed as an object \
+                             with only a raw field.",
+                        )
+                        .short("f")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["plain", "json"])
+                        .default_value("plain"),
+                )
+                .arg(log_file_arg())
                 .display_order(1),
         )
         .subcommand(
             SubCommand::with_name("timings")
                 .about("If timings is enabled, generate a report and return the URL.")
                 .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("OUT")
+                        .help(
+                            "Also save the report data to this file as JSON, for when the \
+                             report URL can't be opened. The data is saved as the server returns \
+                             it, paperd doesn't download anything from the URL. Servers which \
+                             can only share the report through the URL can't be used with this.",
+                        )
+                        .short("o")
+                        .long("out")
+                        .value_name("FILE")
+                        .takes_value(true),
+                )
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("ping")
+                .about(
+                    "Check the server is responding, and measure how long it takes for a \
+                     message to reach the server and come back.",
+                )
+                .arg(&sock_arg)
+                .display_order(1),
+        )
+        .subcommand(
+            SubCommand::with_name("version")
+                .about("Print the version of paperd, or of the running server with --server.")
+                .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("SERVER")
+                        .help(
+                            "Ask the running server for the Minecraft version and Paper build \
+                             it's running, rather than printing the version of paperd.",
+                        )
+                        .long("server"),
+                )
                 .display_order(1),
         )
         .subcommand(
             SubCommand::with_name("start")
                 .about("Start the MC server in the background.")
-                .arg(tail_arg(
-                    "Tail the server log after starting the server. Press C-c to \
-                     quit (will NOT stop the server).",
-                ))
+                .arg(
+                    tail_arg(
+                        "Stay attached to the server log after starting the server, \
+                         including everything the server logged while starting up. Press C-c \
+                         to detach (will NOT stop the server).",
+                    )
+                    .visible_alias("attach"),
+                )
                 .java_run(start_after)
                 .arg(
                     Arg::with_name("KEEP_ALIVE")
@@ -132,6 +557,117 @@ LICENSE:
                         .short("k")
                         .long("keep-alive"),
                 )
+                .arg(
+                    Arg::with_name("RESTART_ON_CRASH")
+                        .help(
+                            "Restart the server when it crashes, like --keep-alive, but wait a \
+                             while before starting it again, 5 seconds at first and doubling \
+                             with each crash up to a minute. Once the server has crashed \
+                             MAX_RETRIES times within 10 minutes it stays stopped, rather than \
+                             crashing in a loop. Default is 5 retries, set another number with \
+                             --restart-on-crash=MAX_RETRIES. Each crash is logged to \
+                             paper.crash.log in the working directory.",
+                        )
+                        .long("restart-on-crash")
+                        .value_name("MAX_RETRIES")
+                        .takes_value(true)
+                        .min_values(0)
+                        .max_values(1)
+                        .require_equals(true)
+                        .conflicts_with("KEEP_ALIVE"),
+                )
+                .arg(
+                    Arg::with_name("STOP_WHEN_EMPTY")
+                        .help(
+                            "Stop the server once no players have been online for this long, \
+                             such as 15m or 1h. Plain numbers are seconds. The time only counts \
+                             once the server has finished starting, and starts over whenever a \
+                             player joins. Checked every 10 seconds.",
+                        )
+                        .long("stop-when-empty")
+                        .value_name("DURATION")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ON_READY")
+                        .help(
+                            "A shell command to run every time the server finishes starting, \
+                             including after it has been restarted, for example to register the \
+                             server with a proxy. The PID of the server's Java process is passed \
+                             in the PAPERD_PID environment variable. The command is run in the \
+                             working directory of the server, a failing command is logged but \
+                             doesn't affect the server.",
+                        )
+                        .long("on-ready")
+                        .value_name("COMMAND")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ON_EXIT")
+                        .help(
+                            "A shell command to run every time the server exits, whether it was \
+                             stopped, crashed, or is about to be restarted, for example to make a \
+                             backup. The server's exit code is passed in the PAPERD_EXIT_CODE \
+                             environment variable, and PAPERD_RESTARTING is 1 if the server will \
+                             be started again once the command finishes, 0 otherwise. The command \
+                             is run in the working directory of the server, failures are logged to \
+                             paper.hooks.log there.",
+                        )
+                        .long("on-exit")
+                        .value_name("COMMAND")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ABSTRACT_SOCKET")
+                        .help(
+                            "Linux only. Have the server listen on an abstract socket named after \
+                             a hash of the socket file's path, instead of on the socket file \
+                             itself. The server stays reachable even if the socket file is deleted \
+                             or replaced, which otherwise leaves a running server unreachable \
+                             until it's restarted. Abstract sockets aren't protected by file \
+                             permissions, so the server only accepts connections from the user it \
+                             runs as, and they don't show up in the server's directory. Commands \
+                             must refer to the server by the same path it was started with, \
+                             symlinks are resolved but hard links and bind mounts are not.",
+                        )
+                        .long("abstract-socket"),
+                )
+                .arg(
+                    Arg::with_name("SOCKET_MODE")
+                        .help(
+                            "The permissions of the server's socket file, as an octal number like \
+                             chmod takes. Default is 600. Connecting to the socket takes write \
+                             permission on it, and anyone who can connect can run any command on \
+                             the server, as if they were an operator. Only give the group write \
+                             access, such as with 660, if everyone in it should have that power, \
+                             and never give it to other users on a shared machine.",
+                        )
+                        .long("socket-mode")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .conflicts_with("ABSTRACT_SOCKET"),
+                )
+                .arg(
+                    Arg::with_name("WAIT")
+                        .help(
+                            "Don't return until the server has finished starting and is ready \
+                             for commands. Exits with a non-zero code if the server exits while \
+                             starting, or if it hasn't finished starting within --wait-timeout.",
+                        )
+                        .long("wait")
+                        .conflicts_with("TAIL"),
+                )
+                .arg(
+                    Arg::with_name("WAIT_TIMEOUT")
+                        .help(
+                            "How many seconds --wait waits for the server to finish starting. \
+                             Default is 300.",
+                        )
+                        .long("wait-timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .requires("WAIT"),
+                )
                 .display_order(2),
         )
         .subcommand(
@@ -170,6 +706,50 @@ LICENSE:
                         .short("-k")
                         .long("--kill"),
                 )
+                .arg(
+                    Arg::with_name("TIMEOUT")
+                        .help(
+                            "How many seconds to wait for the server to stop gracefully. With \
+                             --force the server is killed once this runs out. Default is 30.",
+                        )
+                        .short("t")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true)
+                        .conflicts_with("KILL"),
+                )
+                .arg(
+                    Arg::with_name("YES")
+                        .help(
+                            "Don't ask for confirmation before the server may be killed with \
+                             --force or --kill. paperd only asks when stdin is a terminal, so \
+                             scripts don't need this.",
+                        )
+                        .short("y")
+                        .long("yes"),
+                )
+                .arg(
+                    Arg::with_name("MESSAGE")
+                        .help(
+                            "Broadcast this message to the players with say right before \
+                             stopping the server, such as the reason for the shutdown, so \
+                             they're not dropped without warning.",
+                        )
+                        .short("m")
+                        .long("message")
+                        .value_name("TEXT")
+                        .takes_value(true)
+                        .conflicts_with("KILL"),
+                )
+                .arg(
+                    Arg::with_name("DRY_RUN")
+                        .help(
+                            "Print the socket file, PID file and PID of the server which would \
+                             be stopped, and how it would be stopped, then exit without stopping \
+                             it.",
+                        )
+                        .long("dry-run"),
+                )
                 .group(ArgGroup::with_name("FORCE_ARGS").args(&["FORCE", "KILL"]))
                 .display_order(3),
         )
@@ -187,6 +767,126 @@ LICENSE:
                     "Tail the server log after asking the server to restart. Press \
                      C-c to quit.",
                 ))
+                .arg(tail_lines_arg())
+                .arg(
+                    Arg::with_name("DELAY")
+                        .help(
+                            "Restart the server after a delay, such as 90, 30s, 5m or 1h. Plain \
+                             numbers are seconds. Players are warned with a broadcast when the \
+                             restart is scheduled, and again as it gets close. Press C-c before \
+                             the delay is over to cancel the restart.",
+                        )
+                        .long("in")
+                        .value_name("DELAY")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("DRY_RUN")
+                        .help(
+                            "Print the socket file and PID of the server which would be \
+                             restarted, and how it would be started again, then exit without \
+                             restarting it.",
+                        )
+                        .long("dry-run"),
+                )
+                .display_order(3),
+        )
+        .subcommand(
+            SubCommand::with_name("wait")
+                .about(
+                    "Block until the server has stopped, then exit with the exit code of the \
+                     paperd process which ran it, for example `paperd start && paperd wait`. \
+                     Restarts don't count as stopping. If the server has already stopped, this \
+                     exits right away with the last recorded exit code. Servers not started by \
+                     paperd have no exit code recorded, so this exits with 0 once they're gone.",
+                )
+                .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("TIMEOUT")
+                        .help(
+                            "Give up after waiting this many seconds, and exit with 124. By \
+                             default this waits forever.",
+                        )
+                        .short("t")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .takes_value(true),
+                )
+                .display_order(3),
+        )
+        .subcommand(
+            SubCommand::with_name("cleanup")
+                .about(
+                    "Remove the PID file, socket file and launch state left behind by a server \
+                     which didn't shut down cleanly, for example because it was killed. This \
+                     refuses to do anything if the server is still running.",
+                )
+                .arg(&sock_arg)
+                .arg(
+                    Arg::with_name("PID_FILE")
+                        .help(
+                            "The PID file of the server to clean up. The socket file and launch \
+                             state next to it are removed as well. If not set, the files next to \
+                             the socket file are used.",
+                        )
+                        .short("p")
+                        .long("pid-file")
+                        .takes_value(true)
+                        .conflicts_with("SOCK"),
+                )
+                .display_order(3),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .about(
+                    "List every instance registered with --instance, with the PID, state and \
+                     working directory of each. An instance is dead if it has a PID file but its \
+                     process isn't running anymore, and stopped if it has no PID file.",
+                )
+                .arg(
+                    Arg::with_name("JSON")
+                        .help("Print the instances as a JSON array rather than as a table.")
+                        .long("json"),
+                )
+                .display_order(3),
+        )
+        .subcommand(
+            SubCommand::with_name("property")
+                .about(
+                    "Read or change the server's settings in server.properties. The file in the \
+                     server's working directory is edited directly, so this works whether or not \
+                     the server is running, but the server has to be restarted to use new \
+                     values. Comments and the order of the file are kept.",
+                )
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("get")
+                        .about("Print the value of a property, or every property if none is given.")
+                        .arg(&sock_arg)
+                        .arg(
+                            Arg::with_name("KEY")
+                                .help("The name of the property, e.g. view-distance.")
+                                .index(1),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("Set the value of a property, adding it if it isn't in the file.")
+                        .arg(&sock_arg)
+                        .arg(
+                            Arg::with_name("KEY")
+                                .help("The name of the property, e.g. view-distance.")
+                                .required(true)
+                                .index(1),
+                        )
+                        .arg(
+                            Arg::with_name("VALUE")
+                                .help("The new value of the property.")
+                                .required(true)
+                                .allow_hyphen_values(true)
+                                .index(2),
+                        ),
+                )
                 .display_order(3),
         )
         .subcommand(
@@ -196,7 +896,7 @@ LICENSE:
                 .arg(
                     Arg::with_name("SHELL")
                         .help("The shell to generate the completion script for")
-                        .possible_values(&["bash", "zsh", "fish"]),
+                        .possible_values(&["bash", "zsh", "fish", "powershell", "elvish"]),
                 )
                 .after_help(COMPLETIONS_HELP)
                 .display_order(4),
@@ -223,9 +923,55 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
                     .long("jvm")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("DRY_RUN")
+                    .help(
+                        "Print the JVM, working directory, jar, and full command line which \
+                         would be used to run the server, then exit without running it.",
+                    )
+                    .long("dry-run"),
+            )
+            .arg(
+                Arg::with_name("MIN_JAVA")
+                    .help(
+                        "The minimum Java version required to run the server. paperd checks the \
+                         version of the JVM before starting the server, and warns if it is older \
+                         than this. Default is 17.",
+                    )
+                    .long("min-java")
+                    .value_name("VERSION")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("STRICT_JAVA")
+                    .help(
+                        "Refuse to start the server if the JVM is older than the minimum Java \
+                         version, rather than just warning about it.",
+                    )
+                    .long("strict"),
+            )
+            .arg(
+                Arg::with_name("ALLOW_ROOT")
+                    .help(
+                        "Allow running the server as root. paperd refuses to run the server as \
+                         root by default, as anything the server or its plugins do would have \
+                         full control over the machine. Some setups, such as containers, run \
+                         everything as root on purpose.",
+                    )
+                    .long("allow-root"),
+            )
             .arg(
                 Arg::with_name("JAR")
-                    .help("The jar to run.")
+                    .help(
+                        "The jar to run. Relative paths are resolved against the working \
+                         directory if it is set. If this is a directory, the newest jar in it \
+                         is run, judged by the version numbers in the file names, such as \
+                         paper-1.20.4-123.jar, then by modification time. Only jars with the \
+                         same name in front of the version are compared, Paper jars are picked if \
+                         the directory holds jars of other names as well. The newest jar is \
+                         picked again each time the server restarts, so an updater can drop a \
+                         new jar into the directory.",
+                    )
                     .long("jar")
                     .takes_value(true)
                     .default_value("paperclip.jar"),
@@ -246,13 +992,55 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
                         "Use a default set of recommended JVM arguments (Aikar's flags) \
                          with the specified amount of memory. The format should be something \
                          like 500m or 10G. It's recommended to provide as much memory as possible \
-                         up to 10G. You may not provide custom arguments if defaults are used.",
+                         up to 10G. You may not provide custom arguments if defaults are used, \
+                         use --jvm-arg to add arguments to the defaults instead.",
                     )
                     .short("d")
                     .long("default-args")
                     .value_name("MEMORY")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("DEFAULT_ARGS_PERCENT")
+                    .help(
+                        "Use the same default JVM arguments as --default-args, with the amount of \
+                         memory set to a percentage of the system's total memory, for example 75. \
+                         The result is kept between 1G and 31G, and the amount chosen is printed \
+                         when starting. This can't be combined with --default-args.",
+                    )
+                    .long("default-args-percent")
+                    .value_name("PERCENT")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("GC")
+                    .help(
+                        "The garbage collector to use default JVM arguments for. g1 uses Aikar's \
+                         flags, zgc and shenandoah use flags for those collectors instead, which \
+                         keep pauses shorter on large heaps. The amount of memory is still set \
+                         with --default-args. Default is g1.",
+                    )
+                    .long("gc")
+                    .value_name("GC")
+                    .takes_value(true)
+                    .possible_values(&["g1", "zgc", "shenandoah"]),
+            )
+            .arg(
+                Arg::with_name("JVM_ARG")
+                    .help(
+                        "Add a JVM argument after the default ones, for example \
+                         '--jvm-arg -Dfile.encoding=UTF-8'. Repeat this argument for each JVM \
+                         argument to add. These come last, so they can override the default \
+                         arguments. This can't be combined with custom JVM arguments or \
+                         --args-file.",
+                    )
+                    .long("jvm-arg")
+                    .value_name("ARG")
+                    .takes_value(true)
+                    .allow_hyphen_values(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
             .arg(
                 Arg::with_name("CUSTOM_ARGS")
                     .help(
@@ -265,6 +1053,18 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
                     .allow_hyphen_values(true)
                     .multiple(true),
             )
+            .arg(
+                Arg::with_name("ARGS_FILE")
+                    .help(
+                        "Read the JVM arguments from a file, one argument per line. Blank lines \
+                         and lines starting with # are ignored. Like custom JVM arguments, this \
+                         specifies all JVM arguments which will be passed, so it may not be used \
+                         with -d or --default-args, or with custom arguments.",
+                    )
+                    .long("args-file")
+                    .value_name("FILE")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("SERVER_ARGS")
                     .help(
@@ -280,6 +1080,33 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
                     .multiple(true)
                     .number_of_values(1),
             )
+            .arg(
+                Arg::with_name("ENV")
+                    .help(
+                        "Set an environment variable for the server, on top of the environment \
+                         paperd is run with. Repeat this argument for each variable, for example \
+                         '--env TMPDIR=/var/tmp --env HTTP_PROXY=proxy:8080'.",
+                    )
+                    .long("env")
+                    .value_name("KEY=VALUE")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("PID_WRITE_MODE")
+                    .help(
+                        "What to do when the working directory already has a PID file. fail \
+                         refuses to start the server, overwrite replaces the PID file even if its \
+                         process is still running, and reuse-if-dead only replaces the PID file \
+                         if its process isn't running anymore.",
+                    )
+                    .long("pid-write-mode")
+                    .value_name("MODE")
+                    .takes_value(true)
+                    .possible_values(&["fail", "overwrite", "reuse-if-dead"])
+                    .default_value("reuse-if-dead"),
+            )
             .arg(
                 Arg::with_name("CONFIG_FILE")
                     .help(
@@ -293,11 +1120,6 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
                     .long("config-file")
                     .takes_value(true),
             )
-            .group(
-                ArgGroup::with_name("JVM_ARGS")
-                    .arg("DEFAULT_ARGS")
-                    .arg("CUSTOM_ARGS"),
-            )
             .after_help(after_text);
     }
 
@@ -307,13 +1129,33 @@ impl<'a, 'b> PaperArg<'a, 'b> for App<'a, 'b> {
             SubCommand::with_name("console")
                 .about("Attach to the console of the running MC server.")
                 .arg(arg)
+                .arg(
+                    Arg::with_name("PLAIN")
+                        .help(
+                            "Use a plain line based console instead of the full terminal UI. \
+                             The server's log is printed as it's written and each line read \
+                             from stdin is sent to the server as a command. Press C-c to \
+                             detach, this does not stop the server.",
+                        )
+                        .short("p")
+                        .long("plain"),
+                )
                 .display_order(1),
         );
     }
 
     #[cfg(not(feature = "console"))]
-    fn console(self, _: &Arg<'a, 'b>) -> Self {
-        return self;
+    fn console(self, arg: &Arg<'a, 'b>) -> Self {
+        return self.subcommand(
+            SubCommand::with_name("console")
+                .about(
+                    "Attach to the console of the running MC server. The server's log is \
+                     printed as it's written and each line read from stdin is sent to the \
+                     server as a command. Press C-c to detach, this does not stop the server.",
+                )
+                .arg(arg)
+                .display_order(1),
+        );
     }
 }
 
@@ -321,6 +1163,30 @@ fn tail_arg(message: &str) -> Arg {
     return Arg::with_name("TAIL").help(message).short("t").long("tail");
 }
 
+fn tail_lines_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("TAIL_LINES")
+        .help(
+            "With --tail, print this many lines from before the log is followed first, for \
+             context, like tail -n N -f. Default is 0. `paperd log --tail` uses --lines for this.",
+        )
+        .long("tail-lines")
+        .value_name("N")
+        .takes_value(true)
+        .requires("TAIL");
+}
+
+fn log_file_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("LOG_FILE")
+        .help(
+            "The log file of the server. If not set, the PAPERD_LOG environment variable will be \
+             checked. If neither is set, logs/latest.log in the working directory of the server \
+             is used.",
+        )
+        .long("log-file")
+        .value_name("FILE")
+        .takes_value(true);
+}
+
 fn run_after_text(command_text: &str) -> String {
     return format!(
         r#"EXAMPLES:
@@ -334,6 +1200,12 @@ fn run_after_text(command_text: &str) -> String {
     OR
         $ paperd {cmd} -- -Xmx5G -Xms5G
 
+    To size the memory from the system's total memory instead, use --default-args-percent:
+        $ paperd {cmd} --default-args-percent 75
+
+    To add arguments to the defaults rather than replacing them, use --jvm-arg:
+        $ paperd {cmd} -d 10G --jvm-arg -Dfile.encoding=UTF-8
+
 CONFIG FILE:
     You may pass options to this command using a JSON configuration file instead of command line
     arguments using the --config-file argument. When using this argument the config file values
@@ -346,10 +1218,12 @@ CONFIG FILE:
     * workingDir | This is equivalent to the -w or --working-dir argument.
     * serverArgs | This is equivalent to the -s or --server-arg argument.
     * jvmArgs    | This is equivalent to the CUSTOM_ARGS argument.
+    * minJava    | This is equivalent to the --min-java argument.
 
     The serverArgs and jvmArgs fields are lists of arguments, where each entry in the list is one
     argument to be passed to either the server or the JVM respectively. This includes any whitespace
-    which may appear in the argument. All other fields are JSON strings.
+    which may appear in the argument. The minJava field is a JSON number. All other fields are JSON
+    strings.
 
     Example JSON file:
     {{
@@ -373,8 +1247,8 @@ pub static COMPLETIONS_HELP: &str = r"DISCUSSION:
     configuration may also determine where these scripts need to be
     placed.
 
-    Here are some common set ups for the three supported shells under
-    Unix and similar operating systems (such as GNU/Linux).
+    Here are some common set ups for the supported shells under Unix
+    and similar operating systems (such as GNU/Linux).
 
     BASH:
 
@@ -438,6 +1312,35 @@ pub static COMPLETIONS_HELP: &str = r"DISCUSSION:
 
     for the new completions to take affect.
 
+    POWERSHELL:
+
+    PowerShell (pwsh) has no directory it loads completions from, they
+    are loaded from your profile instead. Save the script next to your
+    profile:
+
+        PS> paperd completions powershell > (Join-Path (Split-Path $PROFILE) paperd.ps1)
+
+    Then add the following line to your profile, which is found at the
+    path in `$PROFILE`:
+
+        . (Join-Path (Split-Path $PROFILE) paperd.ps1)
+
+    Start a new PowerShell session for the completions to take affect.
+
+    ELVISH:
+
+    Elvish loads modules from `~/.config/elvish/lib` (or `~/.elvish/lib`
+    for older versions). Run the command:
+
+        $ mkdir -p ~/.config/elvish/lib
+        $ paperd completions elvish > ~/.config/elvish/lib/paperd.elv
+
+    Then add the following line to `~/.config/elvish/rc.elv`:
+
+        use paperd
+
+    Start a new Elvish session for the completions to take affect.
+
     CUSTOM LOCATIONS:
 
     Alternatively, you could save these files to the place of your
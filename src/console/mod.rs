@@ -16,6 +16,7 @@
 pub mod ansi;
 
 use crate::console::ansi::{AnsiCode, MessageElement, StyledMessage};
+use crate::messaging::MessageError;
 use crate::protocol::check_protocol;
 use crate::send::send_command;
 use crate::status::{StatusMessage, StatusMessageResponse};
@@ -292,7 +293,7 @@ impl<'a> Term<'a> {
                 ($stop:ident, $res:expr) => {
                     match $res {
                         Ok(v) => v,
-                        Err(ExitValue::Shutdown) => {
+                        Err(MessageError::Shutdown) => {
                             $stop.store(true, Ordering::SeqCst);
                             break;
                         }
@@ -820,7 +821,7 @@ fn request_completions(
             command: command_text,
         };
         if let Err(e) = sock.send_message(&message) {
-            if let ExitValue::Shutdown = e {
+            if let MessageError::Shutdown = e {
                 stop_bg.store(true, Ordering::SeqCst);
             }
             return;
@@ -832,7 +833,7 @@ fn request_completions(
 
         let received = match sock.receive_message::<TabCompleteMessageResponse>() {
             Ok(resp) => resp,
-            Err(ExitValue::Shutdown) => {
+            Err(MessageError::Shutdown) => {
                 stop_bg.store(true, Ordering::SeqCst);
                 return;
             }
@@ -14,10 +14,12 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use crate::util::ExitValue;
+use nix::libc;
 use nix::sys::stat::{umask, Mode};
-use nix::unistd::{close, fork, setsid, ForkResult};
+use nix::sys::wait::waitpid;
+use nix::unistd::{close, fork, pipe, read, setsid, write, ForkResult};
 use std::io::{stderr, stdin, stdout};
-use std::os::unix::io::AsRawFd;
+use std::os::unix::io::{AsRawFd, RawFd};
 
 pub enum Status {
     CONTINUE,
@@ -27,14 +29,42 @@ pub enum Status {
 /// Forks the currently running process and returns either an error int (to exit with) or a `Status`
 /// telling the caller whether to exit or to continue. The parent process should quit, with the
 /// child process continuing, now as a separate daemon process.
+///
+/// This forks twice, the first child starts a new session and forks again before exiting. The
+/// daemon process is left without being a session leader, so it can never acquire a controlling
+/// terminal. The PID given to the parent in `Status::QUIT` is the PID of the daemon process.
 pub fn run_daemon() -> Result<Status, ExitValue> {
+    // The daemon isn't a child of the parent process, so its PID is sent back through this pipe
+    let (read_fd, write_fd) = match pipe() {
+        Ok(fds) => fds,
+        Err(_) => {
+            eprintln!("Failed to create pipe");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
     // Create a new pid and execute from there
     match fork() {
         Ok(ForkResult::Parent { child }) => {
+            let _ = close(write_fd);
+            // The first child exits as soon as it has forked again, reap it so it doesn't stay
+            // around as a zombie
+            let _ = waitpid(child, None);
+            let pid = read_pid(read_fd);
+            let _ = close(read_fd);
+
             // Continue in the child, we're done in the parent
-            return Ok(Status::QUIT(child.as_raw()));
+            return match pid {
+                Some(pid) => Ok(Status::QUIT(pid)),
+                None => {
+                    eprintln!("Failed to start daemon process");
+                    Err(ExitValue::Code(1))
+                }
+            };
+        }
+        Ok(ForkResult::Child) => {
+            let _ = close(read_fd);
         }
-        Ok(ForkResult::Child) => {}
         Err(_) => {
             eprintln!("Fork failed");
             return Err(ExitValue::Code(1));
@@ -53,6 +83,22 @@ pub fn run_daemon() -> Result<Status, ExitValue> {
         }
     };
 
+    // Fork again so the daemon isn't the session leader
+    match fork() {
+        Ok(ForkResult::Parent { child }) => {
+            let _ = write(write_fd, &child.as_raw().to_ne_bytes());
+            // Exit without running anything else the parent set up, that's left to the daemon
+            unsafe { libc::_exit(0) };
+        }
+        Ok(ForkResult::Child) => {
+            let _ = close(write_fd);
+        }
+        Err(_) => {
+            eprintln!("Fork failed");
+            unsafe { libc::_exit(1) };
+        }
+    }
+
     umask(Mode::from_bits(0o022).unwrap());
 
     // Close stdin, stdout, stderr; we won't be using them from here on
@@ -63,6 +109,18 @@ pub fn run_daemon() -> Result<Status, ExitValue> {
     return Ok(Status::CONTINUE);
 }
 
+fn read_pid(fd: RawFd) -> Option<i32> {
+    let mut buf = [0u8; 4];
+    let mut len = 0;
+    while len < buf.len() {
+        match read(fd, &mut buf[len..]) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => len += n,
+        }
+    }
+    return Some(i32::from_ne_bytes(buf));
+}
+
 fn close_fd<T: AsRawFd>(fd: T) {
     let _ = close(fd.as_raw_fd());
 }
@@ -0,0 +1,97 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::client::{Client, PaperError};
+use crate::messaging::MessageError;
+use crate::send::{check_control_chars, UNREACHABLE_EXIT_CODE};
+use crate::util::{get_sock, ExitError, ExitValue};
+use crate::verbose;
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// Long enough for a `save-all` on a large server
+pub const DEFAULT_EXEC_TIMEOUT: u64 = 60;
+
+/// What `exec` exits with when the server doesn't confirm the command finished within `--timeout`,
+/// the same as coreutils' `timeout` uses. The command may still finish after that.
+pub const EXEC_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Runs a command on the server and waits for the server to confirm it has finished running,
+/// unlike `send`, which returns as soon as the command is delivered. Exits with 0 if the command
+/// succeeded and 1 if it failed, so scripts can rely on e.g. `save-all` having finished.
+pub fn exec(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let command = match sub_m.values_of("COMMAND") {
+        Some(args) => args.collect::<Vec<&str>>().join(" "),
+        None => {
+            eprintln!("No command given.");
+            return Err(ExitValue::Code(1));
+        }
+    };
+    if !sub_m.is_present("RAW") {
+        check_control_chars(&[(1, command.clone())])?;
+    }
+    let timeout = Duration::from_secs(match sub_m.value_of("TIMEOUT") {
+        Some(t) => t.parse::<u64>().conv("Failed to parse --timeout")?,
+        None => DEFAULT_EXEC_TIMEOUT,
+    });
+
+    let (sock, sock_file) = get_sock(sub_m).map_err(|_| ExitValue::Code(UNREACHABLE_EXIT_CODE))?;
+    let client = Client::from_socket(sock, &sock_file)?;
+
+    return exec_command(&client, command.as_str(), timeout);
+}
+
+/// Runs the command and waits for it to finish, printing why if it failed or didn't finish in time.
+pub fn exec_command(client: &Client, command: &str, timeout: Duration) -> Result<(), ExitValue> {
+    let res = match client.exec_command(command, timeout) {
+        Ok(res) => res,
+        Err(PaperError::Message(MessageError::Timeout)) => {
+            eprintln!(
+                "The server didn't confirm the command finished within {} seconds",
+                timeout.as_secs()
+            );
+            return Err(ExitValue::Code(EXEC_TIMEOUT_EXIT_CODE));
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    if res.success {
+        verbose!("Command finished: {}", command);
+        return Ok(());
+    }
+
+    match res.message {
+        Some(message) => eprintln!("Command failed: {}", message),
+        None => eprintln!("Command failed: {}", command),
+    }
+    return Err(ExitValue::Code(1));
+}
+
+// Request
+#[derive(Serialize)]
+pub struct ExecCommandMessage {
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+// Response
+#[derive(Serialize, Deserialize)]
+pub struct ExecCommandMessageResponse {
+    #[serde(rename = "success")]
+    pub success: bool,
+    #[serde(rename = "message", default)]
+    pub message: Option<String>,
+}
@@ -0,0 +1,109 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::util::{ExitError, ExitValue};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Named instances are registered as files in the paperd config directory. Each file is named after
+/// the instance and contains the working directory of that instance's server, which is where the
+/// server's socket and PID files are found.
+fn instances_dir() -> Option<PathBuf> {
+    return env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("paperd").join("instances"));
+}
+
+fn instance_file(name: &str) -> Result<PathBuf, ExitValue> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        eprintln!(
+            "Invalid instance name '{}'. Instance names must not be empty, start with '.', or \
+             contain '/'.",
+            name
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    return match instances_dir() {
+        Some(dir) => Ok(dir.join(name)),
+        None => {
+            eprintln!("Could not find the paperd config directory, neither XDG_CONFIG_HOME nor HOME are set");
+            Err(ExitValue::Code(1))
+        }
+    };
+}
+
+/// Records the working directory of the server for the given instance name.
+pub fn register_instance(name: &str, working_dir: &PathBuf) -> Result<(), ExitValue> {
+    let file = instance_file(name)?;
+    let working_dir = fs::canonicalize(working_dir).conv(format!(
+        "Failed to get full path to working directory {}",
+        working_dir.to_string_lossy()
+    ))?;
+
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir).conv("Failed to create paperd instances directory")?;
+    }
+    fs::write(&file, working_dir.to_string_lossy().as_bytes())
+        .conv(format!("Failed to register instance {}", name))?;
+
+    return Ok(());
+}
+
+/// Returns the working directory of the server registered for the given instance name.
+pub fn find_instance(name: &str) -> Result<PathBuf, ExitValue> {
+    let file = instance_file(name)?;
+    if !file.is_file() {
+        eprintln!(
+            "No instance named '{}' found. Instances are registered when a server is started \
+             with --instance.",
+            name
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    let working_dir =
+        fs::read_to_string(&file).conv(format!("Failed to read instance {}", name))?;
+    return Ok(PathBuf::from(working_dir.trim_end()));
+}
+
+/// Returns the name and working directory of every registered instance, sorted by name.
+pub fn list_instances() -> Result<Vec<(String, PathBuf)>, ExitValue> {
+    let dir = match instances_dir() {
+        Some(dir) => dir,
+        None => {
+            eprintln!("Could not find the paperd config directory, neither XDG_CONFIG_HOME nor HOME are set");
+            return Err(ExitValue::Code(1));
+        }
+    };
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut instances = Vec::new();
+    for entry in fs::read_dir(&dir).conv("Failed to read paperd instances directory")? {
+        let entry = entry.conv("Failed to read paperd instances directory")?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') || !entry.path().is_file() {
+            continue;
+        }
+        instances.push((name.clone(), find_instance(&name)?));
+    }
+
+    instances.sort();
+    return Ok(instances);
+}
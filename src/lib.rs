@@ -0,0 +1,124 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! paperd runs and controls daemonized PaperMC servers. Besides the `paperd` command line tool, the
+//! `client` module can be used to talk to running servers from other programs. The command line
+//! handlers themselves are private, `run` is all the binary needs.
+
+#[macro_use]
+extern crate clap;
+extern crate crossbeam_channel;
+#[cfg(feature = "console")]
+extern crate ncurses;
+extern crate nix;
+extern crate serde;
+extern crate serde_json;
+extern crate shellexpand;
+extern crate signal_hook;
+extern crate sys_info;
+extern crate zip;
+
+mod attach;
+mod backup;
+mod cleanup;
+pub mod client;
+mod cmd;
+#[cfg(feature = "console")]
+mod console;
+mod daemon;
+mod exec;
+mod instance;
+mod list;
+mod log;
+pub mod messages;
+pub mod messaging;
+mod output;
+mod ping;
+mod property;
+mod protocol;
+mod restart;
+mod runner;
+mod send;
+mod status;
+mod stop;
+mod timings;
+mod util;
+mod version;
+mod wait;
+
+pub use crate::util::{server_state, ServerState};
+
+use crate::attach::attach;
+use crate::backup::backup;
+use crate::cleanup::cleanup;
+use crate::cmd::completions;
+#[cfg(feature = "console")]
+use crate::console::console;
+use crate::exec::exec;
+use crate::list::list;
+use crate::log::log;
+use crate::output::{init_color, init_verbosity};
+use crate::ping::ping;
+use crate::property::property;
+use crate::restart::restart;
+use crate::runner::{run_cmd, start};
+use crate::send::send;
+use crate::status::status;
+use crate::stop::stop;
+use crate::timings::timings;
+use crate::util::ExitValue;
+use crate::version::version;
+use crate::wait::wait;
+
+/// Runs the `paperd` command line tool with the process's arguments, returning its exit code.
+pub fn run() -> i32 {
+    let matches = cmd::get_cmd_line_matches();
+    init_verbosity(&matches);
+    init_color(&matches);
+
+    let ret: Result<(), ExitValue> = match matches.subcommand() {
+        ("status", Some(sub_m)) => status(sub_m),
+        ("send", Some(sub_m)) => send(sub_m),
+        ("exec", Some(sub_m)) => exec(sub_m),
+        ("backup", Some(sub_m)) => backup(sub_m),
+        ("log", Some(sub_m)) => log(sub_m),
+        ("start", Some(sub_m)) => start(sub_m),
+        ("run", Some(sub_m)) => run_cmd(sub_m),
+        ("stop", Some(sub_m)) => stop(sub_m),
+        ("restart", Some(sub_m)) => restart(sub_m),
+        ("timings", Some(sub_m)) => timings(sub_m),
+        ("ping", Some(sub_m)) => ping(sub_m),
+        ("version", Some(sub_m)) => version(sub_m),
+        ("cleanup", Some(sub_m)) => cleanup(sub_m),
+        ("list", Some(sub_m)) => list(sub_m),
+        ("property", Some(sub_m)) => property(sub_m),
+        ("wait", Some(sub_m)) => wait(sub_m),
+        #[cfg(feature = "console")]
+        ("console", Some(sub_m)) if !sub_m.is_present("PLAIN") => console(sub_m),
+        ("console", Some(sub_m)) => attach(sub_m),
+        ("completions", Some(sub_m)) => completions(sub_m),
+        _ => {
+            // This shouldn't happen, clap will error if no command is provided
+            eprint!("Unknown command");
+            Err(ExitValue::Code(1))
+        }
+    };
+
+    return match ret {
+        Ok(()) => 0,
+        Err(ExitValue::Code(c)) => c,
+        Err(ExitValue::Shutdown) => 0,
+    };
+}
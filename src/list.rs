@@ -0,0 +1,122 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::info;
+use crate::instance::list_instances;
+use crate::runner::PID_FILE_NAME;
+use crate::util::{server_state, ExitValue, ServerState};
+use clap::ArgMatches;
+use serde::Serialize;
+
+pub fn list(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let instances: Vec<InstanceInfo> = list_instances()?
+        .into_iter()
+        .map(|(name, working_dir)| {
+            let (pid, state) = match server_state(working_dir.join(PID_FILE_NAME)) {
+                ServerState::Running { pid } => (Some(pid.as_raw()), InstanceState::Running),
+                ServerState::StalePidFile { pid } => (Some(pid.as_raw()), InstanceState::Dead),
+                ServerState::NotRunning | ServerState::NoPidFile => (None, InstanceState::Stopped),
+            };
+            InstanceInfo {
+                name,
+                pid,
+                state,
+                working_dir: working_dir.to_string_lossy().into_owned(),
+            }
+        })
+        .collect();
+
+    if sub_m.is_present("JSON") {
+        return match serde_json::to_string_pretty(&instances) {
+            Ok(json) => {
+                println!("{}", json);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Failed to serialize JSON: {}", e);
+                Err(ExitValue::Code(1))
+            }
+        };
+    }
+
+    if instances.is_empty() {
+        info!("No instances found. Instances are registered when a server is started with --instance.");
+        return Ok(());
+    }
+
+    let name_width = instances
+        .iter()
+        .map(|i| i.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "{:<name_width$}  {:>7}  {:<7}  {}",
+        "NAME",
+        "PID",
+        "STATE",
+        "WORKING DIR",
+        name_width = name_width
+    );
+    for instance in &instances {
+        let pid = instance
+            .pid
+            .map_or_else(|| "-".to_string(), |pid| pid.to_string());
+        println!(
+            "{:<name_width$}  {:>7}  {:<7}  {}",
+            instance.name,
+            pid,
+            instance.state.name(),
+            instance.working_dir,
+            name_width = name_width
+        );
+    }
+
+    return Ok(());
+}
+
+#[derive(Serialize)]
+struct InstanceInfo {
+    #[serde(rename = "name")]
+    name: String,
+    #[serde(rename = "pid")]
+    pid: Option<i32>,
+    #[serde(rename = "state")]
+    state: InstanceState,
+    #[serde(rename = "workingDir")]
+    working_dir: String,
+}
+
+/// `Dead` means there is a PID file, but its process isn't running anymore, the server most likely
+/// crashed or was killed.
+#[derive(Serialize)]
+enum InstanceState {
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "dead")]
+    Dead,
+    #[serde(rename = "stopped")]
+    Stopped,
+}
+
+impl InstanceState {
+    fn name(&self) -> &'static str {
+        return match self {
+            InstanceState::Running => "running",
+            InstanceState::Dead => "dead",
+            InstanceState::Stopped => "stopped",
+        };
+    }
+}
@@ -13,19 +13,93 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::util::{find_program, find_sock_file, ExitError, ExitValue};
+use crate::output::{colored, use_color, Color};
+use crate::util::{
+    find_pid_file, find_program, find_sock_file, get_sock_from_file_direct, local_tm,
+    parse_duration, server_state, server_working_dir, sock_file_path, ExitError, ExitValue,
+    ServerState,
+};
+use crate::verbose;
 use clap::ArgMatches;
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use signal_hook::iterator::Signals;
+use signal_hook::SIGINT;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::process::ExitStatusExt;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use std::{env, io};
 
-pub fn log(sub_m: &ArgMatches) -> Result<(), ExitValue> {
-    let sock_file = find_sock_file(sub_m)?;
-    let log_file = find_log_file(&sock_file)?;
+const DEFAULT_LOG_FILE: &str = "logs/latest.log";
+// How long the log file may be missing while it's being rotated
+const ROTATION_WAIT: Duration = Duration::from_secs(2);
+
+// How much of the log file to read at a time when searching backwards for lines
+const CHUNK_SIZE: u64 = 8 * 1024;
+
+// How often to check the server is still there while following its log
+const SERVER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// While the server is gone, how long to wait before checking again. This doubles after every failed
+// check, up to the maximum
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(8);
+// Enough checks to give the server around a minute to come back
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// How log lines are printed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Lines are printed exactly as the server wrote them.
+    Plain,
+    /// Each line is printed as a JSON object, see `LogLine`.
+    Json,
+}
+
+/// A line of the server log, split into its parts. Paper's log lines look like
+/// `[12:34:56] [Server thread/INFO]: message`, or `[12:34:56 INFO]: message` when the thread isn't
+/// included. Anything else, such as the lines of a stack trace, is kept as is.
+#[derive(Serialize)]
+#[serde(untagged)]
+enum LogLine<'a> {
+    Parsed {
+        #[serde(rename = "time")]
+        time: &'a str,
+        #[serde(rename = "level")]
+        level: &'a str,
+        #[serde(rename = "thread")]
+        thread: Option<&'a str>,
+        #[serde(rename = "message")]
+        message: &'a str,
+    },
+    Raw {
+        #[serde(rename = "raw")]
+        raw: &'a str,
+    },
+}
 
+pub fn log(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    // A stopped server's log is often the one that matters, only following it needs the socket
     let follow = sub_m.is_present("TAIL");
+    let sock_file = if follow {
+        find_sock_file(sub_m)?
+    } else {
+        sock_file_path(sub_m)?
+    };
+    let log_file = find_log_file(sub_m, &sock_file)?;
+
     let lines = match sub_m.value_of("LINES") {
         Some(l) => l
-            .parse::<i32>()
+            .parse::<usize>()
             .conv("Failed to parse command line argument")?,
         None => {
             eprintln!("No value provided for --lines argument");
@@ -33,63 +107,729 @@ pub fn log(sub_m: &ArgMatches) -> Result<(), ExitValue> {
         }
     };
 
-    return tail(log_file, lines, follow);
+    let format = match sub_m.value_of("FORMAT") {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Plain,
+    };
+    let filter = sub_m.value_of("LEVEL").map(LevelFilter::new);
+
+    if follow {
+        return follow_server(log_file, lines, &sock_file, format, filter);
+    }
+
+    let include_rotated = sub_m.is_present("INCLUDE_ROTATED");
+    let last_lines = match sub_m.value_of("SINCE") {
+        Some(since) => {
+            let now = local_time_of_day();
+            read_lines_since(&log_file, parse_since(since, now)?, now).map(|lines| match filter {
+                Some(mut filter) => lines.into_iter().filter(|l| filter.matches(l)).collect(),
+                None => lines,
+            })
+        }
+        None => read_log_lines(&log_file, lines, filter, include_rotated),
+    }
+    .conv(format!(
+        "Failed to read log file {}",
+        log_file.to_string_lossy()
+    ))?;
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for line in last_lines {
+        // stdout being closed early (piping into head, for example) isn't an error
+        if writeln!(out, "{}", format_line(&line, format)).is_err() {
+            break;
+        }
+    }
+
+    return Ok(());
 }
 
-pub fn find_log_file<P: AsRef<Path>>(sock_file: P) -> Result<PathBuf, ExitValue> {
-    let sock_file = sock_file.as_ref();
-    return match sock_file.parent().map(|p| p.join("logs/latest.log")) {
-        Some(f) => Ok(f),
-        None => {
-            eprintln!("Failed to find log file in logs/latest.log");
-            Err(ExitValue::Code(1))
+/// Reads the last `count` lines of a file. The file is read backwards from the end in chunks until
+/// enough lines have been found, so this stays fast even for very large log files.
+fn read_last_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    let end = fs::metadata(path)?.len();
+    return read_lines_before(path, end, count);
+}
+
+/// Same as `read_last_lines`, but for the lines in front of the byte offset `end`.
+fn read_lines_before(path: &Path, end: u64, count: usize) -> io::Result<Vec<String>> {
+    if count == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut file = File::open(path)?;
+    let mut pos = end;
+
+    // Chunks are collected back to front
+    let mut chunks = Vec::<Vec<u8>>::new();
+    let mut newlines: usize = 0;
+
+    // One more newline than lines requested is needed, as the line in front of the first newline
+    // found may only be partially read. If the file ends with a newline that will also be counted,
+    // but that's fine, it just means we have the complete line ready early
+    while pos > 0 && newlines <= count {
+        let size = min(CHUNK_SIZE, pos);
+        pos -= size;
+
+        let mut chunk = vec![0; size as usize];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk)?;
+
+        newlines += chunk.iter().filter(|b| **b == b'\n').count();
+        chunks.push(chunk);
+    }
+
+    let data: Vec<u8> = chunks.into_iter().rev().flatten().collect();
+    let text = String::from_utf8_lossy(&data);
+
+    // lines() handles files which don't end in a newline, as well as CRLF line endings
+    let lines: Vec<&str> = text.lines().collect();
+    let first = lines.len().saturating_sub(count);
+
+    return Ok(lines[first..].iter().map(|l| l.to_string()).collect());
+}
+
+/// Reads the last `count` lines of the log which pass the filter. With `include_rotated`, if the
+/// log file doesn't have enough of them, the rest are read from the log files the server rotated
+/// out, newest first.
+fn read_log_lines(
+    path: &Path,
+    count: usize,
+    filter: Option<LevelFilter>,
+    include_rotated: bool,
+) -> io::Result<Vec<String>> {
+    let mut lines = match filter {
+        // Without a filter the file can be read backwards, which is a lot faster for big logs
+        None => read_last_lines(path, count)?,
+        Some(_) => read_last_matching_lines(BufReader::new(File::open(path)?), count, filter)?,
+    };
+    if !include_rotated {
+        return Ok(lines);
+    }
+
+    for rotated in rotated_log_files(path)? {
+        if lines.len() >= count {
+            break;
         }
+
+        // Compressed files can't be read backwards, so they're decompressed front to back
+        let reader = BufReader::new(GzDecoder::new(File::open(&rotated)?));
+        let mut older = read_last_matching_lines(reader, count - lines.len(), filter)?;
+        verbose!(
+            "Read {} lines from rotated log file {}",
+            older.len(),
+            rotated.display()
+        );
+        older.append(&mut lines);
+        lines = older;
+    }
+
+    return Ok(lines);
+}
+
+/// The log files rotated out next to the live log, newest first. The server names them after the
+/// day they were rotated with a counter for each day, such as `2020-05-17-2.log.gz`.
+fn rotated_log_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     };
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let file = entry?.path();
+        let key = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(rotated_log_key)
+            .map(|(day, count)| (day.to_string(), count));
+        if let Some(key) = key {
+            files.push((key, file));
+        }
+    }
+
+    files.sort_by(|a, b| b.0.cmp(&a.0));
+    return Ok(files.into_iter().map(|(_, file)| file).collect());
 }
 
-pub fn tail<P: AsRef<Path>>(path: P, lines: i32, follow: bool) -> Result<(), ExitValue> {
-    let path = path.as_ref();
-    if !path.is_file() {
-        eprintln!("file could not be found: {}", path.to_string_lossy());
-        return Err(ExitValue::Code(1));
+/// Splits the name of a rotated log file into its day and counter, so they sort in the order they
+/// were rotated.
+fn rotated_log_key(name: &str) -> Option<(&str, u32)> {
+    let stem = name.strip_suffix(".log.gz")?;
+    let (day, count) = stem.split_at(stem.rfind('-')?);
+    let count = count[1..].parse::<u32>().ok()?;
+
+    let is_day = day.len() == 10
+        && day.char_indices().all(|(i, c)| {
+            if i == 4 || i == 7 {
+                c == '-'
+            } else {
+                c.is_ascii_digit()
+            }
+        });
+    return if is_day { Some((day, count)) } else { None };
+}
+
+/// Reads the last `count` lines which pass the filter by reading front to back, keeping only the
+/// last `count` lines in memory.
+fn read_last_matching_lines<R: BufRead>(
+    reader: R,
+    count: usize,
+    mut filter: Option<LevelFilter>,
+) -> io::Result<Vec<String>> {
+    let mut lines = VecDeque::with_capacity(count);
+    for line in reader.split(b'\n') {
+        let line = line?;
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches('\r');
+        if count == 0 || !filter.as_mut().map_or(true, |f| f.matches(text)) {
+            continue;
+        }
+
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        lines.push_back(text.to_string());
     }
 
-    let tail_prog = match find_program(&[("PATH", "tail")]) {
-        Some(t) => t,
-        None => {
-            eprintln!("Failed to find 'tail' program on the PATH");
+    return Ok(lines.into_iter().collect());
+}
+
+/// Parses the `--since` argument into the age in seconds of the oldest line to print. Either a
+/// duration such as `10m`, or a time of day such as `14:30` or `14:30:00`. A time later than `now`,
+/// the current time of day, refers to that time yesterday.
+fn parse_since(text: &str, now: i64) -> Result<i64, ExitValue> {
+    if !text.contains(':') {
+        return Ok(parse_duration(text)
+            .conv("Failed to parse --since")?
+            .as_secs() as i64);
+    }
+
+    let parts = text
+        .split(':')
+        .map(|part| part.parse::<i64>())
+        .collect::<Result<Vec<i64>, _>>()
+        .conv("Failed to parse --since")?;
+    let time = match parts.as_slice() {
+        [h, m] if *h < 24 && *m < 60 => h * 3600 + m * 60,
+        [h, m, s] if *h < 24 && *m < 60 && *s < 60 => h * 3600 + m * 60 + s,
+        _ => {
+            eprintln!(
+                "Failed to parse --since: {} is not a valid time of day",
+                text
+            );
             return Err(ExitValue::Code(1));
         }
     };
 
-    let line_string = lines.to_string();
-    let mut args = Vec::<&str>::new();
-    args.push("-n");
-    args.push(line_string.as_str());
-    if follow {
-        args.push("-F");
+    return Ok((now - time).rem_euclid(SECONDS_PER_DAY));
+}
+
+/// Reads the lines logged within the last `max_age` seconds before `now`. Log lines only include
+/// the time of day, so the file is read backwards from the end, working out how old each line is
+/// from the time between it and the line after it. Lines without a time, such as stack traces,
+/// belong to the line before them.
+fn read_lines_since(path: &Path, max_age: i64, now: i64) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let mut pos = file.metadata()?.len();
+
+    let mut last_time: Option<i64> = None;
+    let mut age: i64 = 0;
+
+    // Lines are collected back to front. Lines without a time are held back until the line they
+    // belong to shows whether they're inside the window
+    let mut lines = Vec::<String>::new();
+    let mut pending = Vec::<String>::new();
+
+    // The start of the earliest chunk read so far, which may only be part of a line
+    let mut data = Vec::<u8>::new();
+    let mut at_end = true;
+
+    'read: loop {
+        if pos > 0 {
+            let size = min(CHUNK_SIZE, pos);
+            pos -= size;
+
+            let mut chunk = vec![0; size as usize];
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut chunk)?;
+            chunk.extend_from_slice(&data);
+            data = chunk;
+        }
+
+        loop {
+            // The first line of the file has no newline in front of it
+            let start = match data.iter().rposition(|b| *b == b'\n') {
+                Some(i) => i + 1,
+                None if pos == 0 => 0,
+                None => break,
+            };
+            let line = String::from_utf8_lossy(&data[start..])
+                .trim_end_matches('\r')
+                .to_string();
+            data.truncate(start.saturating_sub(1));
+
+            // The newline at the very end of the file doesn't start another line
+            let skip = at_end && line.is_empty();
+            at_end = false;
+            if !skip {
+                match line_time(&line) {
+                    Some(time) => {
+                        age += time_between(time, last_time.unwrap_or(now));
+                        last_time = Some(time);
+                        if age > max_age {
+                            break 'read;
+                        }
+                        lines.append(&mut pending);
+                        lines.push(line);
+                    }
+                    None => pending.push(line),
+                }
+            }
+
+            if start == 0 {
+                // Nothing came before these lines, so there's no time to tell them apart by
+                lines.append(&mut pending);
+                break 'read;
+            }
+        }
+    }
+
+    lines.reverse();
+    return Ok(lines);
+}
+
+/// The seconds from the time of day `earlier` to `later`. A line a few seconds older than the line
+/// in front of it is just written out of order, for example by a thread which took its time before
+/// logging, so that counts as no time at all. Only a step back of more than half a day means the
+/// log crossed midnight.
+fn time_between(earlier: i64, later: i64) -> i64 {
+    let step = later - earlier;
+    if step < -SECONDS_PER_DAY / 2 {
+        return step + SECONDS_PER_DAY;
+    }
+    return step.max(0);
+}
+
+/// The time of day of a log line in seconds, if the line has one.
+fn line_time(line: &str) -> Option<i64> {
+    let time = match parse_line(line) {
+        LogLine::Parsed { time, .. } => time,
+        LogLine::Raw { .. } => return None,
+    };
+
+    let mut parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (h, m, s) = (parts.next()??, parts.next()??, parts.next()??);
+    return Some(h * 3600 + m * 60 + s);
+}
+
+/// The current local time of day in seconds, the same clock the server writes its log with.
+fn local_time_of_day() -> i64 {
+    let tm = local_tm();
+    return (tm.tm_hour * 3600 + tm.tm_min * 60 + tm.tm_sec) as i64;
+}
+
+fn format_line(line: &str, format: LogFormat) -> String {
+    if format == LogFormat::Json {
+        // Serializing a struct of strings can't fail
+        return serde_json::to_string(&parse_line(line)).unwrap_or_default();
+    }
+
+    if !use_color() {
+        return line.to_string();
+    }
+
+    // Only the level is colored, the rest of the line is put back together exactly as it was
+    return match parse_line(line) {
+        LogLine::Parsed {
+            time,
+            level,
+            thread: Some(thread),
+            message,
+        } => format!(
+            "[{}] [{}/{}]: {}",
+            time,
+            thread,
+            color_level(level),
+            message
+        ),
+        LogLine::Parsed {
+            time,
+            level,
+            thread: None,
+            message,
+        } => format!("[{} {}]: {}", time, color_level(level), message),
+        LogLine::Raw { raw } => raw.to_string(),
+    };
+}
+
+/// Only lets through log lines at or above a level, for `--level`. Lines without a level, such as
+/// the lines of a stack trace, belong to the line in front of them, so they're let through if that
+/// line was.
+#[derive(Clone, Copy)]
+pub struct LevelFilter {
+    min: u8,
+    keep: bool,
+}
+
+impl LevelFilter {
+    pub fn new(min_level: &str) -> Self {
+        return LevelFilter {
+            min: level_rank(min_level).unwrap_or(0),
+            keep: false,
+        };
+    }
+
+    /// Whether to print the line. Lines must be passed in the order they were logged.
+    pub fn matches(&mut self, line: &str) -> bool {
+        if let LogLine::Parsed { level, .. } = parse_line(line) {
+            // Levels paperd doesn't know about are let through rather than risk hiding problems
+            self.keep = level_rank(level).map_or(true, |rank| rank >= self.min);
+        }
+        return self.keep;
+    }
+}
+
+/// Orders the levels of log4j, which Paper uses, along with the names java.util.logging uses for
+/// the same levels, as plugins still log with those.
+fn level_rank(level: &str) -> Option<u8> {
+    return match level.to_ascii_uppercase().as_str() {
+        "TRACE" | "FINEST" | "FINER" => Some(0),
+        "DEBUG" | "FINE" | "CONFIG" => Some(1),
+        "INFO" => Some(2),
+        "WARN" | "WARNING" => Some(3),
+        "ERROR" | "SEVERE" => Some(4),
+        "FATAL" => Some(5),
+        _ => None,
+    };
+}
+
+fn color_level(level: &str) -> String {
+    return match level {
+        "ERROR" | "SEVERE" | "FATAL" => colored(level, Color::Red),
+        "WARN" | "WARNING" => colored(level, Color::Yellow),
+        _ => level.to_string(),
+    };
+}
+
+fn parse_line(line: &str) -> LogLine<'_> {
+    let raw = LogLine::Raw { raw: line };
+
+    let (header, rest) = match line.strip_prefix('[').and_then(|l| split_once(l, "]")) {
+        Some(parts) => parts,
+        None => return raw,
+    };
+
+    // [12:34:56 INFO]: message
+    if let Some(message) = rest.strip_prefix(": ") {
+        return match split_once(header, " ") {
+            Some((time, level)) if is_time(time) && !level.is_empty() => LogLine::Parsed {
+                time,
+                level,
+                thread: None,
+                message,
+            },
+            _ => raw,
+        };
+    }
+
+    // [12:34:56] [Server thread/INFO]: message
+    if !is_time(header) {
+        return raw;
+    }
+    let (source, message) = match rest.strip_prefix(" [").and_then(|r| split_once(r, "]: ")) {
+        Some(parts) => parts,
+        None => return raw,
+    };
+    // Thread names may contain a slash, the level never does
+    return match source.rfind('/') {
+        Some(i) if i + 1 < source.len() => LogLine::Parsed {
+            time: header,
+            level: &source[i + 1..],
+            thread: Some(&source[..i]),
+            message,
+        },
+        _ => raw,
+    };
+}
+
+fn split_once<'a>(text: &'a str, sep: &str) -> Option<(&'a str, &'a str)> {
+    return text.find(sep).map(|i| (&text[..i], &text[i + sep.len()..]));
+}
+
+/// Whether the text is a time of the form `HH:mm:ss`.
+fn is_time(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    return bytes.len() == 8
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            2 | 5 => *b == b':',
+            _ => b.is_ascii_digit(),
+        });
+}
+
+/// Finds the log file of the server using the `--log-file` argument, then the `PAPERD_LOG`
+/// environment variable, then the default log file in the server's working directory. The working
+/// directory is read from the launch state next to the socket file, so this works no matter where
+/// paperd is run from.
+pub fn find_log_file<P: AsRef<Path>>(
+    sub_m: &ArgMatches,
+    sock_file: P,
+) -> Result<PathBuf, ExitValue> {
+    let sock_file = sock_file.as_ref();
+
+    let log_file = if let Some(file) = sub_m.value_of("LOG_FILE") {
+        PathBuf::from(file)
+    } else if let Some(file) = env::var_os("PAPERD_LOG") {
+        PathBuf::from(file)
+    } else {
+        default_log_file(&server_working_dir(sock_file))
+    };
+
+    if !wait_for_file(&log_file, ROTATION_WAIT) {
+        eprintln!(
+            "No log file found at {}. If the server's log is somewhere else, set it with \
+             --log-file or the PAPERD_LOG environment variable.",
+            log_file.display()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    verbose!("Using log file {}", log_file.display());
+    return Ok(log_file);
+}
+
+/// Waits up to `timeout` for `path` to be a file, returning whether it is. When Paper rotates its
+/// log there's a short window where the log file doesn't exist, and new servers take a moment to
+/// create theirs.
+pub fn wait_for_file(path: &Path, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while !path.is_file() {
+        if start.elapsed() >= timeout {
+            return false;
+        }
+        sleep(Duration::from_millis(50));
+    }
+    return true;
+}
+
+/// Where Paper writes its log by default, relative to the working directory of the server.
+pub fn default_log_file(working_dir: &Path) -> PathBuf {
+    return working_dir.join(DEFAULT_LOG_FILE);
+}
+
+pub fn tail<P: AsRef<Path>>(path: P, lines: usize, follow: bool) -> Result<(), ExitValue> {
+    return run_tail(path.as_ref(), tail_args(lines, follow));
+}
+
+/// Follows the file starting at the given byte offset, rather than some number of lines from the
+/// end. The `context` lines in front of the offset are printed first.
+pub fn tail_from<P: AsRef<Path>>(path: P, offset: u64, context: usize) -> Result<(), ExitValue> {
+    let lines = read_lines_before(path.as_ref(), offset, context).conv(format!(
+        "Failed to read log file {}",
+        path.as_ref().display()
+    ))?;
+    for line in lines {
+        println!("{}", line);
     }
 
-    let result = Command::new(&tail_prog).args(args).arg(&path).spawn();
+    // tail counts bytes from 1
+    let args = vec![
+        "-c".to_string(),
+        format!("+{}", offset + 1),
+        "-F".to_string(),
+    ];
+    return run_tail(path.as_ref(), args);
+}
+
+/// The `--tail-lines` argument of commands which can follow the log after doing something else.
+pub fn tail_lines(sub_m: &ArgMatches) -> Result<usize, ExitValue> {
+    return match sub_m.value_of("TAIL_LINES") {
+        Some(lines) => lines.parse::<usize>().conv("Failed to parse --tail-lines"),
+        None => Ok(0),
+    };
+}
+
+/// Follows the log of the server on `sock_file` until the server is gone for good. tail itself copes
+/// with the log being rotated, but it would happily wait on the log of a dead server forever. A
+/// restart takes the server away for a while, as does the PID file being rewritten, so a missing
+/// server is checked for again with increasing delays before giving up.
+pub fn follow_server<P: AsRef<Path>>(
+    path: P,
+    lines: usize,
+    sock_file: &Path,
+    format: LogFormat,
+    filter: Option<LevelFilter>,
+) -> Result<(), ExitValue> {
+    let path = path.as_ref();
+
+    // tail can only pick the last lines of the file, not the last lines which pass the filter, so
+    // those are printed here, and tail starts following where they end
+    let args = match filter {
+        Some(filter) => {
+            let end = fs::metadata(path)
+                .map(|m| m.len())
+                .conv(format!("Failed to read log file {}", path.display()))?;
+            let file =
+                File::open(path).conv(format!("Failed to read log file {}", path.display()))?;
+            let last_lines =
+                read_last_matching_lines(BufReader::new(file.take(end)), lines, Some(filter))
+                    .conv(format!("Failed to read log file {}", path.display()))?;
+            for line in last_lines {
+                println!("{}", format_line(&line, format));
+            }
+            // tail counts bytes from 1
+            vec!["-c".to_string(), format!("+{}", end + 1), "-F".to_string()]
+        }
+        None => tail_args(lines, true),
+    };
 
-    let mut child = match result {
+    // C-c is delivered to both us and tail, so don't let it kill paperd before tail has exited
+    let signals = Signals::new(&[SIGINT]).conv("Failed to register signal handlers")?;
+
+    // tail can write plain lines straight to stdout, otherwise they have to go through us
+    let stdout = if format == LogFormat::Plain && !use_color() && filter.is_none() {
+        Stdio::inherit()
+    } else {
+        Stdio::piped()
+    };
+    let mut child = match spawn_tail_with_args(path, args, stdout) {
         Ok(c) => c,
-        Err(err) => {
+        Err(e) => {
+            signals.close();
+            return Err(e);
+        }
+    };
+
+    let printer = child.stdout.take().map(|output| {
+        return thread::spawn(move || print_formatted(output, format, filter));
+    });
+
+    let mut attempts: u32 = 0;
+    let mut delay = RECONNECT_DELAY;
+    let mut next_check = Instant::now() + SERVER_CHECK_INTERVAL;
+    let mut offline_since = Instant::now();
+
+    let result = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Ok(status),
+            Ok(None) => {}
+            Err(e) => break Err(e),
+        }
+
+        if Instant::now() < next_check {
+            sleep(Duration::from_millis(100));
+            continue;
+        }
+
+        if server_alive(sock_file) {
+            if attempts > 0 {
+                verbose!("Server is back, continuing to follow the log");
+            }
+            attempts = 0;
+            delay = RECONNECT_DELAY;
+            next_check = Instant::now() + SERVER_CHECK_INTERVAL;
+            continue;
+        }
+
+        if attempts == 0 {
+            offline_since = Instant::now();
+        }
+        if attempts == MAX_RECONNECT_ATTEMPTS {
+            let _ = child.kill();
+            let _ = child.wait();
+            signals.close();
             eprintln!(
-                "Failed to tail log file {}: {}",
-                path.to_string_lossy(),
-                err
+                "The server on socket {} has been gone for {} seconds, it doesn't seem to be \
+                 coming back. Stopped following the log.",
+                sock_file.display(),
+                offline_since.elapsed().as_secs()
             );
             return Err(ExitValue::Code(1));
         }
+
+        attempts += 1;
+        verbose!(
+            "Server is not running, checking again in {}ms ({}/{})",
+            delay.as_millis(),
+            attempts,
+            MAX_RECONNECT_ATTEMPTS
+        );
+        next_check = Instant::now() + delay;
+        delay = min(delay * 2, MAX_RECONNECT_DELAY);
+    };
+
+    signals.close();
+    // tail has exited, so the printer reaches the end of its output and finishes on its own
+    if let Some(printer) = printer {
+        let _ = printer.join();
+    }
+
+    return tail_result(path, result);
+}
+
+fn print_formatted<R: Read>(output: R, format: LogFormat, mut filter: Option<LevelFilter>) {
+    let stdout = io::stdout();
+    let mut reader = BufReader::new(output);
+    let mut line = Vec::<u8>::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(|c| c == '\n' || c == '\r');
+        if !filter.as_mut().map_or(true, |f| f.matches(text)) {
+            continue;
+        }
+        let mut out = stdout.lock();
+        if writeln!(out, "{}", format_line(text, format)).is_err() || out.flush().is_err() {
+            return;
+        }
+    }
+}
+
+/// The server counts as alive if it accepts connections on its socket, or if the process in its PID
+/// file is still running. The PID file is read again every time, as it's rewritten on restart.
+fn server_alive(sock_file: &Path) -> bool {
+    if get_sock_from_file_direct(sock_file).is_ok() {
+        return true;
+    }
+
+    return match find_pid_file(sock_file).map(server_state) {
+        Some(ServerState::Running { .. }) => true,
+        _ => false,
     };
+}
 
-    return match child.wait().map(|status| status.code().unwrap_or(1)) {
+fn run_tail(path: &Path, args: Vec<String>) -> Result<(), ExitValue> {
+    // C-c is delivered to both us and tail, so don't let it kill paperd before tail has exited
+    let signals = Signals::new(&[SIGINT]).conv("Failed to register signal handlers")?;
+
+    let mut child = match spawn_tail_with_args(path, args, Stdio::inherit()) {
+        Ok(c) => c,
+        Err(e) => {
+            signals.close();
+            return Err(e);
+        }
+    };
+
+    let result = child.wait();
+    signals.close();
+
+    return tail_result(path, result);
+}
+
+fn tail_result(path: &Path, result: io::Result<ExitStatus>) -> Result<(), ExitValue> {
+    return match result {
         Ok(status) => {
-            if status == 0 {
+            // Being interrupted is the normal way to stop following the log
+            if status.success() || status.signal() == Some(SIGINT) {
                 Ok(())
             } else {
-                Err(ExitValue::Code(status))
+                Err(ExitValue::Code(status.code().unwrap_or(1)))
             }
         }
         Err(err) => {
@@ -102,3 +842,408 @@ pub fn tail<P: AsRef<Path>>(path: P, lines: i32, follow: bool) -> Result<(), Exi
         }
     };
 }
+
+/// Starts `tail` on the given file without waiting for it. The caller is responsible for waiting on
+/// or killing the returned child.
+pub fn spawn_tail(path: &Path, lines: usize, follow: bool) -> Result<Child, ExitValue> {
+    return spawn_tail_with_args(path, tail_args(lines, follow), Stdio::inherit());
+}
+
+fn tail_args(lines: usize, follow: bool) -> Vec<String> {
+    let mut args = vec!["-n".to_string(), lines.to_string()];
+    if follow {
+        // Follow the file by name rather than by descriptor. Paper replaces latest.log when it
+        // rotates the log, -F makes tail notice that and start again from the beginning of the new
+        // file, the same goes for the file being truncated or briefly missing
+        args.push("-F".to_string());
+    }
+    return args;
+}
+
+fn spawn_tail_with_args(path: &Path, args: Vec<String>, stdout: Stdio) -> Result<Child, ExitValue> {
+    if !wait_for_file(path, ROTATION_WAIT) {
+        eprintln!("file could not be found: {}", path.to_string_lossy());
+        return Err(ExitValue::Code(1));
+    }
+
+    let tail_prog = match find_program(&[("PATH", "tail")]) {
+        Some(t) => t,
+        None => {
+            eprintln!("Failed to find 'tail' program on the PATH");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    return match Command::new(&tail_prog)
+        .args(args)
+        .arg(path)
+        .stdout(stdout)
+        .spawn()
+    {
+        Ok(c) => Ok(c),
+        Err(err) => {
+            eprintln!(
+                "Failed to tail log file {}: {}",
+                path.to_string_lossy(),
+                err
+            );
+            Err(ExitValue::Code(1))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Writes `text` to a new log file, returning its path.
+    fn log_file(text: &str) -> PathBuf {
+        let count = FILE_COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("paperd-test-{}-{}.log", process::id(), count));
+        fs::write(&path, text).unwrap();
+        return path;
+    }
+
+    fn last_lines(text: &str, count: usize) -> Vec<String> {
+        let path = log_file(text);
+        let lines = read_last_lines(&path, count).unwrap();
+        let _ = fs::remove_file(&path);
+        return lines;
+    }
+
+    fn lines_since(text: &str, max_age: i64, now: &str) -> Vec<String> {
+        let path = log_file(text);
+        let lines = read_lines_since(&path, max_age, clock(now)).unwrap();
+        let _ = fs::remove_file(&path);
+        return lines;
+    }
+
+    fn clock(time: &str) -> i64 {
+        return line_time(&format!("[{} INFO]: ", time)).unwrap();
+    }
+
+    /// The time, level, thread and message of a line, or `None` if it's kept raw.
+    fn parts(line: &str) -> Option<(&str, &str, Option<&str>, &str)> {
+        return match parse_line(line) {
+            LogLine::Parsed {
+                time,
+                level,
+                thread,
+                message,
+            } => Some((time, level, thread, message)),
+            LogLine::Raw { raw } => {
+                assert_eq!(raw, line);
+                None
+            }
+        };
+    }
+
+    #[test]
+    fn parse_threaded() {
+        assert_eq!(
+            parts("[12:34:56] [Server thread/INFO]: Done (1.5s)!"),
+            Some(("12:34:56", "INFO", Some("Server thread"), "Done (1.5s)!"))
+        );
+        assert_eq!(
+            parts("[12:34:56] [Server thread/WARN]: "),
+            Some(("12:34:56", "WARN", Some("Server thread"), ""))
+        );
+    }
+
+    #[test]
+    fn parse_unthreaded() {
+        assert_eq!(
+            parts("[12:34:56 INFO]: [Plugin] Enabling Plugin v1.0"),
+            Some(("12:34:56", "INFO", None, "[Plugin] Enabling Plugin v1.0"))
+        );
+    }
+
+    #[test]
+    fn parse_thread_with_slash() {
+        assert_eq!(
+            parts("[12:34:56] [Netty Server IO #1/a/b/ERROR]: oops"),
+            Some(("12:34:56", "ERROR", Some("Netty Server IO #1/a/b"), "oops"))
+        );
+    }
+
+    #[test]
+    fn parse_raw() {
+        assert_eq!(parts("java.lang.NullPointerException: null"), None);
+        assert_eq!(parts("\tat Main.main(Main.java:1) [?:?]"), None);
+        assert_eq!(parts("[12:34:56] [Server thread]: no level"), None);
+        assert_eq!(parts("[12:34:56] [Server thread/]: empty level"), None);
+        assert_eq!(parts("[1:34:56 INFO]: bad time"), None);
+        assert_eq!(parts("[12:34:56 INFO] no colon"), None);
+        assert_eq!(parts(""), None);
+    }
+
+    #[test]
+    fn format_plain_and_json() {
+        let line = "[12:34:56] [Server thread/INFO]: Done";
+        assert_eq!(format_line(line, LogFormat::Plain), line);
+        assert_eq!(
+            format_line(line, LogFormat::Json),
+            r#"{"time":"12:34:56","level":"INFO","thread":"Server thread","message":"Done"}"#
+        );
+        assert_eq!(
+            format_line("[12:34:56 WARN]: \"quoted\"", LogFormat::Json),
+            r#"{"time":"12:34:56","level":"WARN","thread":null,"message":"\"quoted\""}"#
+        );
+        assert_eq!(
+            format_line("\tat Main.main(Main.java:1)", LogFormat::Json),
+            r#"{"raw":"\tat Main.main(Main.java:1)"}"#
+        );
+    }
+
+    fn filter(min_level: &str, lines: &[&str]) -> Vec<String> {
+        let mut filter = LevelFilter::new(min_level);
+        return lines
+            .iter()
+            .filter(|l| filter.matches(l))
+            .map(|l| l.to_string())
+            .collect();
+    }
+
+    #[test]
+    fn level_filter() {
+        let lines = [
+            "[12:00:00 DEBUG]: a",
+            "[12:00:01 INFO]: b",
+            "[12:00:02 WARN]: c",
+            "[12:00:03 ERROR]: d",
+        ];
+        assert_eq!(filter("warn", &lines), &lines[2..]);
+        assert_eq!(filter("INFO", &lines), &lines[1..]);
+        assert_eq!(filter("fatal", &lines), Vec::<String>::new());
+    }
+
+    #[test]
+    fn level_filter_continuation() {
+        let lines = [
+            "[12:00:00] [Server thread/INFO]: starting",
+            "\tat Info.trace(Info.java:1)",
+            "[12:00:01] [Server thread/ERROR]: boom",
+            "java.lang.Exception",
+            "\tat Main.main(Main.java:1)",
+            "[12:00:02] [Server thread/INFO]: done",
+            "\tat Info.trace(Info.java:2)",
+        ];
+        assert_eq!(filter("error", &lines), &lines[2..5]);
+    }
+
+    #[test]
+    fn level_filter_jul_names() {
+        let lines = [
+            "[12:00:00 FINE]: a",
+            "[12:00:01 CONFIG]: b",
+            "[12:00:02 WARNING]: c",
+            "[12:00:03 SEVERE]: d",
+        ];
+        assert_eq!(filter("warn", &lines), &lines[2..]);
+        assert_eq!(filter("error", &lines), &lines[3..]);
+        assert_eq!(filter("info", &lines), &lines[2..]);
+    }
+
+    #[test]
+    fn level_filter_unknown_level() {
+        let lines = [
+            "[12:00:00 INFO]: a",
+            "[12:00:01 NOTICE]: b",
+            "continued",
+            "[12:00:02 INFO]: c",
+        ];
+        assert_eq!(filter("error", &lines), &lines[1..3]);
+    }
+
+    #[test]
+    fn last_lines_small_file() {
+        assert_eq!(last_lines("a\nb\nc\n", 2), vec!["b", "c"]);
+        assert_eq!(last_lines("a\r\nb\r\nc\r\n", 2), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn last_lines_no_trailing_newline() {
+        assert_eq!(last_lines("a\nb\nc", 2), vec!["b", "c"]);
+        assert_eq!(last_lines("a", 2), vec!["a"]);
+    }
+
+    #[test]
+    fn last_lines_count() {
+        assert!(last_lines("a\nb\nc\n", 0).is_empty());
+        assert_eq!(last_lines("a\nb\nc\n", 10), vec!["a", "b", "c"]);
+        assert!(last_lines("", 10).is_empty());
+    }
+
+    #[test]
+    fn last_lines_across_chunks() {
+        // 10 bytes a line, so the chunks start in the middle of a line
+        let lines: Vec<String> = (0..2000).map(|i| format!("line {:04}", i)).collect();
+        let text = lines.join("\n") + "\n";
+        assert_ne!(text.len() as u64 % CHUNK_SIZE, 0);
+
+        for count in &[1, 818, 819, 820, 1639, 2000, 2001] {
+            let first = lines.len().saturating_sub(*count);
+            assert_eq!(last_lines(&text, *count), &lines[first..], "{}", count);
+        }
+    }
+
+    #[test]
+    fn last_lines_long_line() {
+        // A single line longer than a chunk
+        let long = "x".repeat(CHUNK_SIZE as usize * 2 + 100);
+        let text = format!("first\n{}\nlast\n", long);
+        assert_eq!(last_lines(&text, 2), vec![long.as_str(), "last"]);
+        assert_eq!(last_lines(&text, 3), vec!["first", long.as_str(), "last"]);
+    }
+
+    #[test]
+    fn rotated_log_names() {
+        assert_eq!(
+            rotated_log_key("2020-05-17-2.log.gz"),
+            Some(("2020-05-17", 2))
+        );
+        assert_eq!(
+            rotated_log_key("2020-05-17-10.log.gz"),
+            Some(("2020-05-17", 10))
+        );
+        assert_eq!(rotated_log_key("2020-05-17.log.gz"), None);
+        assert_eq!(rotated_log_key("2020-05-17-x.log.gz"), None);
+        assert_eq!(rotated_log_key("2020-5-17-1.log.gz"), None);
+        assert_eq!(rotated_log_key("crash-2020-05-17-1.log.gz"), None);
+        assert_eq!(rotated_log_key("2020-05-17-1.log"), None);
+        assert_eq!(rotated_log_key("world-backup-1.tar.gz"), None);
+    }
+
+    #[test]
+    fn rotated_log_order() {
+        let count = FILE_COUNT.fetch_add(1, Ordering::SeqCst);
+        let dir = env::temp_dir().join(format!("paperd-test-{}-{}", process::id(), count));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &[
+            "latest.log",
+            "2020-05-17-2.log.gz",
+            "2020-05-16-3.log.gz",
+            "2020-05-17-10.log.gz",
+            "world-backup-1.tar.gz",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let files = rotated_log_files(&dir.join("latest.log"));
+        let _ = fs::remove_dir_all(&dir);
+
+        let names: Vec<String> = files
+            .unwrap()
+            .iter()
+            .map(|f| f.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "2020-05-17-10.log.gz",
+                "2020-05-17-2.log.gz",
+                "2020-05-16-3.log.gz"
+            ]
+        );
+    }
+
+    #[test]
+    fn since_duration() {
+        assert_eq!(parse_since("30", 0).ok(), Some(30));
+        assert_eq!(parse_since("10m", 0).ok(), Some(600));
+        assert_eq!(parse_since("2h", 0).ok(), Some(7200));
+        assert!(parse_since("18446744073709551615h", 0).is_err());
+        assert!(parse_since("soon", 0).is_err());
+    }
+
+    #[test]
+    fn since_clock_time() {
+        let now = clock("12:00:00");
+        assert_eq!(parse_since("11:30", now).ok(), Some(1800));
+        assert_eq!(parse_since("11:30:15", now).ok(), Some(1785));
+        assert_eq!(parse_since("12:00", now).ok(), Some(0));
+        // Later than now is yesterday
+        assert_eq!(parse_since("12:30", now).ok(), Some(SECONDS_PER_DAY - 1800));
+        assert!(parse_since("24:00", now).is_err());
+        assert!(parse_since("12:60", now).is_err());
+        assert!(parse_since("12:00:00:00", now).is_err());
+    }
+
+    #[test]
+    fn line_times() {
+        assert_eq!(
+            line_time("[12:34:56] [Server thread/INFO]: Done"),
+            Some(45296)
+        );
+        assert_eq!(line_time("[12:34:56 INFO]: Done"), Some(45296));
+        assert_eq!(
+            line_time("\tat java.lang.Thread.run(Thread.java:748)"),
+            None
+        );
+        assert_eq!(line_time(""), None);
+    }
+
+    #[test]
+    fn since_window() {
+        let text = "[11:00:00 INFO]: a\n[11:50:00 INFO]: b\n[11:55:00 INFO]: c\n";
+        assert_eq!(
+            lines_since(text, 600, "12:00:00"),
+            vec!["[11:50:00 INFO]: b", "[11:55:00 INFO]: c"]
+        );
+        assert!(lines_since(text, 60, "12:00:00").is_empty());
+    }
+
+    #[test]
+    fn since_no_trailing_newline() {
+        let text = "[11:00:00 INFO]: a\n[11:50:00 INFO]: b\n[11:55:00 INFO]: c";
+        assert_eq!(
+            lines_since(text, 600, "12:00:00"),
+            vec!["[11:50:00 INFO]: b", "[11:55:00 INFO]: c"]
+        );
+    }
+
+    #[test]
+    fn since_continuation_lines() {
+        let text = "[11:40:00] [Server thread/ERROR]: boom\n\
+                    java.lang.Exception\n\
+                    \tat Main.main(Main.java:1)\n\
+                    [11:55:00] [Server thread/INFO]: c\n";
+        assert_eq!(
+            lines_since(text, 1200, "12:00:00"),
+            vec![
+                "[11:40:00] [Server thread/ERROR]: boom",
+                "java.lang.Exception",
+                "\tat Main.main(Main.java:1)",
+                "[11:55:00] [Server thread/INFO]: c",
+            ]
+        );
+        // The stack trace is as old as the error it belongs to
+        assert_eq!(
+            lines_since(text, 600, "12:00:00"),
+            vec!["[11:55:00] [Server thread/INFO]: c"]
+        );
+    }
+
+    #[test]
+    fn since_crossing_midnight() {
+        let text = "[23:58:00 INFO]: a\n[23:59:30 INFO]: b\n[00:00:30 INFO]: c\n";
+        assert_eq!(
+            lines_since(text, 120, "00:01:00"),
+            vec!["[23:59:30 INFO]: b", "[00:00:30 INFO]: c"]
+        );
+        assert_eq!(lines_since(text, 180, "00:01:00").len(), 3);
+    }
+
+    #[test]
+    fn since_out_of_order() {
+        // A line stamped a second before the one in front of it doesn't end the search
+        let text = "[11:58:00 INFO]: a\n[11:59:31 INFO]: b\n[11:59:30 INFO]: c\n";
+        assert_eq!(lines_since(text, 60, "12:00:00").len(), 2);
+        assert_eq!(lines_since(text, 120, "12:00:00").len(), 2);
+        assert_eq!(lines_since(text, 150, "12:00:00").len(), 3);
+    }
+}
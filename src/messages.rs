@@ -13,12 +13,14 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::exec::ExecCommandMessage;
 use crate::protocol::ProtocolVersionMessage;
 use crate::restart::RestartMessage;
-use crate::send::SendCommandMessage;
+use crate::send::{CaptureCommandMessage, SendCommandMessage};
 use crate::status::StatusMessage;
 use crate::stop::StopMessage;
 use crate::timings::TimingsMessage;
+use crate::version::ServerVersionMessage;
 use serde::Deserialize;
 #[cfg(feature = "console")]
 use {
@@ -26,6 +28,15 @@ use {
     crate::console::LogsMessage, crate::console::TabCompleteMessage,
 };
 
+// The messages and responses used by `client`, the rest are only used by the command line handlers
+pub use crate::exec::ExecCommandMessageResponse;
+pub use crate::ping::PingMessage;
+pub use crate::status::{
+    GcStatus, HeapStatus, MemoryStatus, ServerPhase, StatusMessageResponse, TpsStatus, WorldStatus,
+};
+pub use crate::version::ServerVersionMessageResponse;
+
+/// Messages sent to the server, the type id tells the server how to read the message.
 pub trait MessageHandler {
     fn type_id() -> i64;
 }
@@ -34,9 +45,27 @@ pub trait MessageHandler {
 #[derive(Deserialize)]
 pub struct ServerErrorMessage {
     #[serde(rename = "error")]
-    pub error: Option<String>,
+    pub error: Option<ServerError>,
     #[serde(rename = "shutdown")]
     pub is_shutdown: bool,
+    #[serde(rename = "exitCode", default)]
+    pub exit_code: Option<i32>,
+}
+
+/// Servers either send a plain error message, or an object with an error code and optionally a
+/// message and extra details.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum ServerError {
+    Message(String),
+    Detailed {
+        #[serde(rename = "code")]
+        code: String,
+        #[serde(rename = "message", default)]
+        message: Option<String>,
+        #[serde(rename = "details", default)]
+        details: Option<serde_json::Value>,
+    },
 }
 
 macro_rules! message_version {
@@ -63,3 +92,7 @@ message_version!(6, LogsMessage, console);
 message_version!(7, EndLogsListenerMessage, console);
 message_version!(8, ConsoleStatusMessage, console);
 message_version!(9, TabCompleteMessage, console);
+message_version!(10, PingMessage);
+message_version!(11, ServerVersionMessage);
+message_version!(12, CaptureCommandMessage);
+message_version!(13, ExecCommandMessage);
@@ -13,53 +13,98 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::messages::{MessageHandler, ServerErrorMessage};
-use crate::util::{ExitError, ExitValue};
-use paperd_lib::{close_socket, receive_message, send_message, Message, MessageHeader, Socket};
+use crate::debug;
+use crate::messages::{MessageHandler, ServerError, ServerErrorMessage};
+use crate::util::ExitValue;
+use nix::sys::socket::{shutdown, Shutdown};
+use paperd_lib::{
+    close_socket, receive_message_limited, send_message, Message, MessageHeader, Socket,
+    DEFAULT_MAX_MESSAGE_LENGTH,
+};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::fmt;
+use std::fmt::Display;
+use std::time::{Duration, Instant};
 use {nix::errno::Errno, paperd_lib::Error};
 
 pub struct MessageSocket {
     sock: Socket,
-    pub print_err: bool,
+    closed: bool,
+    max_message_length: usize,
 }
 
-macro_rules! message_resp {
-    ($msg:ident, $self:ident) => {
-        match $msg {
-            Some(m) => m,
-            None => {
-                if $self.print_err {
-                    eprintln!("The Paper server closed the socket");
-                }
-                return Err(ExitValue::Code(1));
-            }
-        }
-    };
+/// Errors which can occur while communicating with the Paper server. Nothing is printed when these
+/// occur, it's up to the caller to decide whether to report them. Converting one into an
+/// `ExitValue` prints the error.
+pub enum MessageError {
+    /// The message could not be serialized to JSON
+    Serialize(serde_json::Error),
+    /// The server is shutting down, either it told us so or it hung up while we were sending
+    Shutdown,
+    /// The server closed the socket without sending a response
+    Closed,
+    /// No message arrived before the caller stopped waiting for one
+    Timeout,
+    /// Part of a message was received, but the rest of it never arrived
+    TransferTimeout,
+    /// The server announced a message longer than the limit set on the socket, with the length it
+    /// announced and the limit. The connection is shut down, as the rest of the message can't be
+    /// skipped
+    TooLarge(i64, usize),
+    /// A system call failed while sending a message
+    Send(Error),
+    /// A system call failed while receiving a message
+    Receive(Error),
+    /// The server responded with an error, not all errors come with a message. The server may also
+    /// say which code paperd should exit with, so scripts can tell errors apart
+    Server {
+        error: Option<ServerError>,
+        exit_code: Option<i32>,
+    },
+    /// The server sent a message with no content
+    Empty,
+    /// The response from the server could not be parsed
+    Parse(serde_json::Error),
 }
 
 impl MessageSocket {
     pub fn new(sock: Socket) -> Self {
         return MessageSocket {
             sock,
-            print_err: true,
+            closed: false,
+            max_message_length: DEFAULT_MAX_MESSAGE_LENGTH,
         };
     }
 
-    pub fn send_message<T>(&self, message: &T) -> Result<(), ExitValue>
+    /// The file descriptor of the underlying socket, for debugging output. Messages should only be
+    /// sent and received through the `MessageSocket` itself.
+    pub fn raw_fd(&self) -> Socket {
+        return self.sock;
+    }
+
+    /// Sets the largest message this socket will receive, in bytes. Defaults to
+    /// `DEFAULT_MAX_MESSAGE_LENGTH`.
+    pub fn set_max_message_length(&mut self, max_length: usize) {
+        self.max_message_length = max_length;
+    }
+
+    /// Closes the underlying socket. This is safe to call more than once, only the first call will
+    /// close the socket, and dropping a closed `MessageSocket` will not close it a second time.
+    pub fn close(&mut self) -> Result<(), Error> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        debug!("socket.close", fd = self.sock);
+        return close_socket(self.sock);
+    }
+
+    pub fn send_message<T>(&self, message: &T) -> Result<(), MessageError>
     where
         T: MessageHandler + Serialize,
     {
-        let msg = match serde_json::to_string(message) {
-            Ok(s) => s,
-            Err(e) => {
-                if self.print_err {
-                    eprintln!("Failed to serialize JSON: {}", e);
-                }
-                return Err(ExitValue::Code(1));
-            }
-        };
+        let msg = serde_json::to_string(message).map_err(MessageError::Serialize)?;
 
         let message = Message {
             header: MessageHeader {
@@ -69,82 +114,101 @@ impl MessageSocket {
             message_text: msg,
         };
 
-        let res = send_message(self.sock, &message);
-        match res {
-            Err(Error::Nix(nix::Error::Sys(Errno::EPIPE), _)) => {
-                if self.print_err {
-                    eprintln!("Socket closed");
-                }
-                return Err(ExitValue::Shutdown);
-            }
-            Err(_) => {
-                if self.print_err {
-                    res.conv("Error attempting to send message to Paper server")?;
-                } else {
-                    res.map_err(|_| ExitValue::Code(1))?;
-                };
-            }
-            _ => {}
-        }
-
-        return Ok(());
+        debug!(
+            "message.send",
+            fd = self.sock,
+            type_id = message.header.message_type,
+            bytes = message.header.message_length
+        );
+        return match send_message(self.sock, &message) {
+            Ok(()) => Ok(()),
+            Err(Error::Nix(nix::Error::Sys(Errno::EPIPE), _)) => Err(MessageError::Shutdown),
+            Err(e) => Err(MessageError::Send(e)),
+        };
     }
 
-    pub fn receive_message<R: DeserializeOwned>(&self) -> Result<R, ExitValue> {
+    pub fn receive_message<R: DeserializeOwned>(&self) -> Result<R, MessageError> {
         return self.receive_loop(|| true);
     }
 
-    pub fn receive_loop<R, F>(&self, keep_waiting_filter: F) -> Result<R, ExitValue>
+    /// Receive a message, giving up if none arrives within `timeout`. The socket has a short receive
+    /// timeout of its own, so this simply polls it until the deadline passes rather than blocking
+    /// indefinitely.
+    pub fn receive_message_timeout<R: DeserializeOwned>(
+        &self,
+        timeout: Duration,
+    ) -> Result<R, MessageError> {
+        let start = Instant::now();
+        return self.receive_loop(|| start.elapsed() < timeout);
+    }
+
+    pub fn receive_loop<R, F>(&self, keep_waiting_filter: F) -> Result<R, MessageError>
     where
         R: DeserializeOwned,
         F: Fn() -> bool,
     {
         let msg = loop {
-            match receive_message(self.sock) {
+            match receive_message_limited(self.sock, self.max_message_length) {
                 Ok(m) => break m,
                 Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), _)) => {
                     if keep_waiting_filter() {
                         continue;
                     } else {
-                        return Err(ExitValue::Code(1));
+                        return Err(MessageError::Timeout);
                     }
                 }
-                Err(Error::Nix(nix::Error::Sys(Errno::UnknownErrno), s)) => {
-                    return Err(Error::Nix(nix::Error::Sys(Errno::EAGAIN), s))
-                        .conv(format!("Timeout occurred during the transfer of a message"));
+                Err(Error::Nix(nix::Error::Sys(Errno::UnknownErrno), _)) => {
+                    return Err(MessageError::TransferTimeout);
                 }
-                Err(e) => {
-                    return Err(e).conv("Error attempting to receive message from Paper server")
+                Err(Error::TooLarge(length, max)) => {
+                    debug!("message.receive", fd = self.sock, bytes = length, max = max);
+                    // Nothing after the header was read, so whatever is read next would start in
+                    // the middle of this message. Shut the connection down so nothing is, the
+                    // socket itself is still closed as usual
+                    let _ = shutdown(self.sock, Shutdown::Both);
+                    return Err(MessageError::TooLarge(length, max));
                 }
+                Err(e) => return Err(MessageError::Receive(e)),
             }
         };
 
-        let msg = message_resp!(msg, self);
-        return self.handle_message(&msg);
+        return match msg {
+            Some(m) => {
+                debug!(
+                    "message.receive",
+                    fd = self.sock,
+                    type_id = m.header.message_type,
+                    bytes = m.header.message_length
+                );
+                self.handle_message(&m)
+            }
+            None => {
+                debug!("message.receive", fd = self.sock, closed = true);
+                Err(MessageError::Closed)
+            }
+        };
     }
 
-    fn handle_message<R: DeserializeOwned>(&self, msg: &Message) -> Result<R, ExitValue> {
+    fn handle_message<R: DeserializeOwned>(&self, msg: &Message) -> Result<R, MessageError> {
         let msg_text = msg.message_text.as_str();
+        if msg_text.trim().is_empty() {
+            return Err(MessageError::Empty);
+        }
 
         return match serde_json::from_str::<R>(msg_text) {
             Ok(r) => Ok(r),
             Err(e) => match serde_json::from_str::<ServerErrorMessage>(msg_text) {
                 Ok(message) => {
                     if message.is_shutdown {
-                        Err(ExitValue::Shutdown)
+                        Err(MessageError::Shutdown)
                     } else {
-                        if self.print_err && message.error.is_some() {
-                            eprintln!("{}", message.error.unwrap());
-                        }
-                        Err(ExitValue::Code(1))
-                    }
-                }
-                Err(_) => {
-                    if self.print_err {
-                        eprintln!("Failed to parse response from server: {}", e);
+                        Err(MessageError::Server {
+                            error: message.error,
+                            exit_code: message.exit_code,
+                        })
                     }
-                    Err(ExitValue::Code(1))
                 }
+                Err(_) => Err(MessageError::Parse(e)),
             },
         };
     }
@@ -152,7 +216,88 @@ impl MessageSocket {
 
 impl Drop for MessageSocket {
     fn drop(&mut self) {
-        self.print_err = false;
-        let _ = close_socket(self.sock);
+        let _ = self.close();
+    }
+}
+
+impl Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            MessageError::Serialize(e) => write!(f, "Failed to serialize JSON: {}", e),
+            MessageError::Shutdown => write!(f, "The Paper server is shutting down"),
+            MessageError::Closed => write!(f, "The Paper server closed the socket"),
+            MessageError::Timeout => write!(f, "Timed out waiting for the Paper server to respond"),
+            MessageError::TransferTimeout => {
+                write!(f, "Timeout occurred during the transfer of a message")
+            }
+            MessageError::TooLarge(length, max) => write!(
+                f,
+                "The Paper server's response is too large: {} bytes, the limit is {} bytes",
+                length, max
+            ),
+            MessageError::Send(e) => write!(
+                f,
+                "Error attempting to send message to Paper server\n  \
+                 Caused by: Error during system call: {}",
+                e
+            ),
+            MessageError::Receive(e) => write!(
+                f,
+                "Error attempting to receive message from Paper server\n  \
+                 Caused by: Error during system call: {}",
+                e
+            ),
+            MessageError::Server { error: Some(e), .. } => write!(f, "{}", e),
+            MessageError::Server { error: None, .. } => {
+                write!(f, "The Paper server responded with an error")
+            }
+            MessageError::Empty => write!(f, "The Paper server sent an empty response"),
+            MessageError::Parse(e) => write!(f, "Failed to parse response from server: {}", e),
+        };
+    }
+}
+
+impl Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (code, message, details) = match self {
+            ServerError::Message(message) => return write!(f, "{}", message),
+            ServerError::Detailed {
+                code,
+                message,
+                details,
+            } => (code, message, details),
+        };
+
+        match message {
+            Some(message) => write!(f, "{} (error code: {})", message, code)?,
+            None => write!(f, "The Paper server responded with error code {}", code)?,
+        }
+
+        return match details {
+            // Strings are printed as-is rather than as a quoted JSON string
+            Some(serde_json::Value::String(details)) => write!(f, "\n  Details: {}", details),
+            Some(serde_json::Value::Null) | None => Ok(()),
+            Some(details) => write!(f, "\n  Details: {}", details),
+        };
+    }
+}
+
+impl From<MessageError> for ExitValue {
+    fn from(e: MessageError) -> Self {
+        return match e {
+            MessageError::Shutdown => ExitValue::Shutdown,
+            MessageError::Server { error, exit_code } => {
+                // If the server didn't say what went wrong there's nothing useful to print
+                if let Some(error) = error {
+                    eprintln!("{}", error);
+                }
+                // An error always exits with a non-zero code
+                ExitValue::Code(exit_code.filter(|c| *c != 0).unwrap_or(1))
+            }
+            e => {
+                eprintln!("{}", e);
+                ExitValue::Code(1)
+            }
+        };
     }
 }
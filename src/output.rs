@@ -0,0 +1,176 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use clap::ArgMatches;
+use nix::libc::STDOUT_FILENO;
+use nix::unistd::isatty;
+use std::env;
+use std::fmt::Display;
+use std::io;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+/// How much diagnostic output paperd prints. This is set once from the global `--quiet`,
+/// `--verbose` and `--log-level` flags. It doesn't affect the actual output of a command, such as
+/// the output of `status` or `log`, or errors which cause paperd to exit.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Verbosity {
+    Quiet = 0,
+    Normal = 1,
+    Verbose = 2,
+    /// Everything `Verbose` prints, plus a trace of each step paperd takes talking to the server
+    Debug = 3,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Global args may be given before or after the subcommand, so check both.
+pub fn init_verbosity(matches: &ArgMatches) {
+    let is_present = |name: &str| {
+        return matches.is_present(name)
+            || matches
+                .subcommand()
+                .1
+                .map_or(false, |sub_m| sub_m.is_present(name));
+    };
+
+    let log_level = matches
+        .subcommand()
+        .1
+        .and_then(|sub_m| sub_m.value_of("LOG_LEVEL"))
+        .or_else(|| matches.value_of("LOG_LEVEL"));
+
+    let verbosity = match log_level {
+        Some("quiet") => Verbosity::Quiet,
+        Some("verbose") => Verbosity::Verbose,
+        Some("debug") => Verbosity::Debug,
+        Some(_) => Verbosity::Normal,
+        None if is_present("QUIET") => Verbosity::Quiet,
+        None if is_present("VERBOSE") => Verbosity::Verbose,
+        None => Verbosity::Normal,
+    };
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    return match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        2 => Verbosity::Verbose,
+        3 => Verbosity::Debug,
+        _ => Verbosity::Normal,
+    };
+}
+
+pub fn is_quiet() -> bool {
+    return verbosity() == Verbosity::Quiet;
+}
+
+/// `println!` for progress and informational messages, these aren't printed with `--quiet`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// `eprintln!` for debugging details, these are only printed with `--verbose` or `--log-level
+/// debug`.
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::output::verbosity() >= $crate::output::Verbosity::Verbose {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Traces a step paperd takes, only printed with `--log-level debug`. Each event is printed on one
+/// line as its name followed by `key=value` fields, so traces sent in by users are easy to grep,
+/// for example `debug!("message.send", type_id = 3, bytes = 42)`.
+#[macro_export]
+macro_rules! debug {
+    ($event:expr $(, $key:ident = $value:expr)* $(,)?) => {
+        if $crate::output::verbosity() >= $crate::output::Verbosity::Debug {
+            $crate::output::print_debug($event, &[$((stringify!($key), $value.to_string())),*]);
+        }
+    };
+}
+
+/// Prints a `debug!` event. Values which are empty or contain whitespace or quotes are quoted, so
+/// each line still splits cleanly into fields.
+pub fn print_debug(event: &str, fields: &[(&str, String)]) {
+    let mut line = format!("debug: {}", event);
+    for (key, value) in fields {
+        if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"') {
+            line.push_str(&format!(" {}={:?}", key, value));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    eprintln!("{}", line);
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Colors used to highlight parts of the output, such as log levels and TPS.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Color {
+    Red = 31,
+    Green = 32,
+    Yellow = 33,
+}
+
+/// Decides whether output is colored from the global `--color` arg. `auto`, the default, colors
+/// output only when stdout is a terminal and the `NO_COLOR` environment variable isn't set.
+pub fn init_color(matches: &ArgMatches) {
+    let value = matches
+        .subcommand()
+        .1
+        .and_then(|sub_m| sub_m.value_of("COLOR"))
+        .or_else(|| matches.value_of("COLOR"));
+
+    let color = match value {
+        Some("always") => true,
+        Some("never") => false,
+        _ => {
+            let no_color = env::var_os("NO_COLOR").map_or(false, |v| !v.is_empty());
+            !no_color && isatty(STDOUT_FILENO).unwrap_or(false)
+        }
+    };
+    COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn use_color() -> bool {
+    return COLOR.load(Ordering::Relaxed);
+}
+
+/// Wraps the text in the escape codes for the color, if output is colored.
+pub fn colored<T: Display>(text: T, color: Color) -> String {
+    if !use_color() {
+        return text.to_string();
+    }
+    return format!("\x1b[{}m{}\x1b[0m", color as u8, text);
+}
+
+/// Prints part of a line of progress, such as the dots printed while waiting for the server. Like
+/// `info!`, nothing is printed with `--quiet`.
+pub fn progress(text: &str) {
+    if !is_quiet() {
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    }
+}
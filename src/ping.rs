@@ -0,0 +1,46 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::client::Client;
+use crate::util::{get_sock, ExitValue};
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+pub fn ping(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let (sock, sock_file) = get_sock(sub_m)?;
+    let client = Client::from_socket(sock, &sock_file)?;
+
+    let time = client.ping()?;
+    println!(
+        "Response from server: time={:.2} ms",
+        time.as_secs_f64() * 1000.0
+    );
+
+    return Ok(());
+}
+
+// Request
+#[derive(Serialize)]
+pub struct PingMessage {
+    #[serde(rename = "payload")]
+    pub payload: u64,
+}
+
+// Response
+#[derive(Serialize, Deserialize)]
+pub struct PingMessageResponse {
+    #[serde(rename = "payload")]
+    pub payload: u64,
+}
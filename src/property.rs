@@ -0,0 +1,460 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::info;
+use crate::util::{server_working_dir, sock_file_path, ExitError, ExitValue};
+use crate::verbose;
+use clap::ArgMatches;
+use nix::unistd::{chown, Gid, Uid};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+const PROPERTIES_FILE_NAME: &str = "server.properties";
+
+/// Reads or changes `server.properties` in the server's working directory. The file is edited
+/// directly rather than through the server, the server only reads it while starting up anyways, and
+/// this way it also works while the server isn't running.
+pub fn property(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    return match sub_m.subcommand() {
+        ("get", Some(sub_m)) => get(sub_m),
+        ("set", Some(sub_m)) => set(sub_m),
+        _ => {
+            // This shouldn't happen, clap will error if no command is provided
+            eprintln!("Unknown command");
+            Err(ExitValue::Code(1))
+        }
+    };
+}
+
+fn get(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let file = find_properties_file(sub_m)?;
+    let text = fs::read_to_string(&file).conv(format!("Failed to read {}", file.display()))?;
+    let lines: Vec<&str> = text.lines().collect();
+    let entries = parse_properties(&lines);
+
+    let key = match sub_m.value_of("KEY") {
+        Some(key) => key,
+        None => {
+            for entry in entries {
+                println!("{}={}", entry.key, entry.value);
+            }
+            return Ok(());
+        }
+    };
+
+    // Like Java's Properties, the last of several entries for the same key is the one that counts
+    return match entries.into_iter().rfind(|e| e.key == key) {
+        Some(entry) => {
+            println!("{}", entry.value);
+            Ok(())
+        }
+        None => {
+            eprintln!("No property {} in {}", key, file.display());
+            Err(ExitValue::Code(1))
+        }
+    };
+}
+
+fn set(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let (key, value) = match (sub_m.value_of("KEY"), sub_m.value_of("VALUE")) {
+        (Some(key), Some(value)) => (key, value),
+        _ => {
+            eprintln!("No property or value given");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    let file = find_properties_file(sub_m)?;
+    let text = fs::read_to_string(&file).conv(format!("Failed to read {}", file.display()))?;
+    let new_text = set_property(&text, key, value);
+    write_atomic(&file, &new_text).conv(format!("Failed to write {}", file.display()))?;
+
+    info!(
+        "Set {} to {}. The server reads {} while starting, so restart it for the change to take \
+         effect.",
+        key, value, PROPERTIES_FILE_NAME
+    );
+    return Ok(());
+}
+
+/// Returns the text of the properties file with the property set to the value. Only the property's
+/// own lines are replaced, comments, the order of everything else and the line endings stay the
+/// same. The last entry for the key is the one which counts, so that's the one which is replaced.
+fn set_property(text: &str, key: &str, value: &str) -> String {
+    let mut lines: Vec<&str> = text.lines().collect();
+    let entries = parse_properties(&lines);
+
+    let new_line = format!("{}={}", escape(key, true), escape(value, false));
+    match entries.iter().rfind(|e| e.key == key) {
+        Some(entry) => {
+            lines.splice(entry.start..entry.end, Some(new_line.as_str()));
+        }
+        None => lines.push(new_line.as_str()),
+    }
+
+    // lines() takes care of both kinds of line endings, but the file should keep the one it had
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut new_text = lines.join(newline);
+    new_text.push_str(newline);
+    return new_text;
+}
+
+/// `server.properties` is in the server's working directory, found the same way as the log file.
+fn find_properties_file(sub_m: &ArgMatches) -> Result<PathBuf, ExitValue> {
+    let sock_file = sock_file_path(sub_m)?;
+    let working_dir = server_working_dir(&sock_file);
+
+    let file = working_dir.join(PROPERTIES_FILE_NAME);
+    if !file.is_file() {
+        eprintln!(
+            "No {} found at {}. The server creates it the first time it starts.",
+            PROPERTIES_FILE_NAME,
+            file.display()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    verbose!("Using properties file {}", file.display());
+    return Ok(file);
+}
+
+/// A property read from the file, along with the lines it was read from. `end` is exclusive.
+struct Property {
+    key: String,
+    value: String,
+    start: usize,
+    end: usize,
+}
+
+/// Parses the lines of a Java properties file. Comments and blank lines are skipped, and values
+/// continued on the next line with a trailing backslash are joined together.
+fn parse_properties(lines: &[&str]) -> Vec<Property> {
+    let mut properties = Vec::new();
+
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        let first = lines[i].trim_start();
+        i += 1;
+        if first.is_empty() || first.starts_with('#') || first.starts_with('!') {
+            continue;
+        }
+
+        let mut logical = first.to_string();
+        while ends_with_continuation(&logical) && i < lines.len() {
+            logical.pop();
+            logical.push_str(lines[i].trim_start());
+            i += 1;
+        }
+
+        let (key, value) = split_property(&logical);
+        properties.push(Property {
+            key: unescape(key),
+            value: unescape(value),
+            start,
+            end: i,
+        });
+    }
+
+    return properties;
+}
+
+/// A line is continued if it ends in an odd number of backslashes, an even number is just escaped
+/// backslashes.
+fn ends_with_continuation(line: &str) -> bool {
+    return line.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1;
+}
+
+/// The key ends at the first unescaped `=`, `:` or whitespace, which may be surrounded by more
+/// whitespace.
+fn split_property(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    let mut key_end = line.len();
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || c.is_whitespace() {
+            key_end = i;
+            break;
+        }
+    }
+
+    let rest = line[key_end..].trim_start();
+    let rest = rest
+        .strip_prefix('=')
+        .or_else(|| rest.strip_prefix(':'))
+        .unwrap_or(rest)
+        .trim_start();
+    return (&line[..key_end], rest);
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\x0c'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let unit = match u16::from_str_radix(&hex, 16) {
+                    Ok(unit) => unit,
+                    Err(_) => {
+                        result.push_str("\\u");
+                        result.push_str(&hex);
+                        continue;
+                    }
+                };
+
+                // Characters outside of the BMP are written as a surrogate pair of two escapes
+                let mut units = vec![unit];
+                if (0xD800..0xDC00).contains(&unit) {
+                    let rest = chars.as_str();
+                    if let Some(low) = rest
+                        .strip_prefix("\\u")
+                        .and_then(|r| r.get(..4))
+                        .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                        .filter(|low| (0xDC00..0xE000).contains(low))
+                    {
+                        units.push(low);
+                        chars = rest[6..].chars();
+                    }
+                }
+                for c in std::char::decode_utf16(units) {
+                    match c {
+                        Ok(c) => result.push(c),
+                        Err(_) => result.push_str(&format!("\\u{}", hex)),
+                    }
+                }
+            }
+            Some(c) => result.push(c),
+            None => {}
+        }
+    }
+    return result;
+}
+
+/// Escapes text the same way Java writes properties files. Anything outside of ASCII is written as
+/// a unicode escape, as older servers read the file as ISO 8859-1.
+fn escape(text: &str, is_key: bool) -> String {
+    let mut result = String::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\x0c' => result.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            // Spaces in keys always end the key, in values only leading ones would be trimmed
+            ' ' if is_key || i == 0 => result.push_str("\\ "),
+            c if !c.is_ascii() || c.is_ascii_control() => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    result.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    return result;
+}
+
+/// Writes the file atomically, so the server never sees it half written. The new file gets the
+/// permissions and owner of the one it replaces, `server.properties` holds the RCON password.
+fn write_atomic(file: &Path, text: &str) -> std::io::Result<()> {
+    let mut tmp_file = file.as_os_str().to_os_string();
+    tmp_file.push(".tmp");
+    let tmp_file = PathBuf::from(tmp_file);
+
+    let metadata = fs::metadata(file)?;
+    let result = write_with_metadata(&tmp_file, text, &metadata).and_then(|_| {
+        return fs::rename(&tmp_file, file);
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_file);
+    }
+    return result;
+}
+
+fn write_with_metadata(file: &Path, text: &str, metadata: &fs::Metadata) -> std::io::Result<()> {
+    // Anything left over from an earlier failed write may have other permissions, and the mode
+    // given here only applies to newly created files
+    let _ = fs::remove_file(file);
+    let mut out = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(metadata.permissions().mode() & 0o777)
+        .open(file)?;
+    out.write_all(text.as_bytes())?;
+
+    // Only root can give the file to another user, otherwise it's ours like the original most
+    // likely was anyways
+    let _ = chown(
+        file,
+        Some(Uid::from_raw(metadata.uid())),
+        Some(Gid::from_raw(metadata.gid())),
+    );
+    // The umask may have taken some of the permissions away
+    fs::set_permissions(file, metadata.permissions())?;
+    return out.sync_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn entries(text: &str) -> Vec<(String, String)> {
+        let lines: Vec<&str> = text.lines().collect();
+        return parse_properties(&lines)
+            .into_iter()
+            .map(|p| (p.key, p.value))
+            .collect();
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        return (key.to_string(), value.to_string());
+    }
+
+    #[test]
+    fn parse_comments_and_separators() {
+        let text = "# comment\n! also a comment\n\na=1\nb : 2\nc 3\n  d=\n";
+        assert_eq!(
+            entries(text),
+            vec![
+                pair("a", "1"),
+                pair("b", "2"),
+                pair("c", "3"),
+                pair("d", "")
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_continuation() {
+        let text = "motd=first \\\n    second\nnext=1\n";
+        let lines: Vec<&str> = text.lines().collect();
+        let properties = parse_properties(&lines);
+        assert_eq!(properties[0].value, "first second");
+        assert_eq!((properties[0].start, properties[0].end), (0, 2));
+        assert_eq!(properties[1].key, "next");
+    }
+
+    #[test]
+    fn continuation_needs_odd_backslashes() {
+        assert!(ends_with_continuation("a\\"));
+        assert!(!ends_with_continuation("a\\\\"));
+        assert!(ends_with_continuation("a\\\\\\"));
+        assert!(!ends_with_continuation("a"));
+    }
+
+    #[test]
+    fn escaped_separators() {
+        assert_eq!(split_property("a\\=b=c"), ("a\\=b", "c"));
+        assert_eq!(split_property("a\\ b c"), ("a\\ b", "c"));
+        assert_eq!(entries("a\\:b=c:d\n"), vec![pair("a:b", "c:d")]);
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for value in &[
+            "plain",
+            " leading space",
+            "a=b:c#d!e",
+            "tab\there",
+            "é",
+            "😀 emoji",
+        ] {
+            assert_eq!(unescape(&escape(value, false)), *value);
+            assert_eq!(unescape(&escape(value, true)), *value);
+        }
+        assert_eq!(escape("😀", false), "\\uD83D\\uDE00");
+    }
+
+    #[test]
+    fn unescape_invalid() {
+        // Unpaired surrogates and broken escapes are kept as they are
+        assert_eq!(unescape("\\uD83Dx"), "\\uD83Dx");
+        assert_eq!(unescape("\\uzzzz"), "\\uzzzz");
+    }
+
+    #[test]
+    fn set_keeps_comments() {
+        let text = "# Minecraft server properties\nmotd=old\n# comment\npvp=true\n";
+        assert_eq!(
+            set_property(text, "motd", "new"),
+            "# Minecraft server properties\nmotd=new\n# comment\npvp=true\n"
+        );
+        assert_eq!(
+            set_property(text, "difficulty", "hard"),
+            "# Minecraft server properties\nmotd=old\n# comment\npvp=true\ndifficulty=hard\n"
+        );
+    }
+
+    #[test]
+    fn set_replaces_continued_lines() {
+        let text = "motd=a \\\n  b\npvp=true\n";
+        assert_eq!(set_property(text, "motd", "c"), "motd=c\npvp=true\n");
+    }
+
+    #[test]
+    fn set_uses_last_entry() {
+        let text = "motd=first\nmotd=second\n";
+        assert_eq!(set_property(text, "motd", "new"), "motd=first\nmotd=new\n");
+    }
+
+    #[test]
+    fn set_keeps_crlf() {
+        let text = "a=1\r\nb=2\r\n";
+        assert_eq!(set_property(text, "a", "3"), "a=3\r\nb=2\r\n");
+    }
+
+    #[test]
+    fn set_round_trips_emoji() {
+        let text = set_property("", "motd", "😀");
+        assert_eq!(entries(&text), vec![pair("motd", "😀")]);
+    }
+
+    #[test]
+    fn write_keeps_permissions() {
+        let file = env::temp_dir().join(format!("paperd-test-{}.properties", process::id()));
+        fs::write(&file, "rcon.password=secret\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomic(&file, "rcon.password=changed\n").unwrap();
+
+        let mode = fs::metadata(&file).unwrap().permissions().mode() & 0o777;
+        let text = fs::read_to_string(&file).unwrap();
+        let _ = fs::remove_file(&file);
+        assert_eq!(mode, 0o600);
+        assert_eq!(text, "rcon.password=changed\n");
+    }
+}
@@ -13,15 +13,19 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::client::PaperError;
+use crate::debug;
 use crate::messaging::MessageSocket;
 use crate::util::{ExitError, ExitValue};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io::Read;
 use std::path::Path;
+use std::time::Duration;
 use zip::ZipArchive;
 
-const PROTOCOL_VERSION: i64 = 1;
+pub const PROTOCOL_VERSION: i64 = 5;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn check_jar_protocol<P: AsRef<Path>>(path: P) -> Result<(), ExitValue> {
     let jar_path = path.as_ref();
@@ -90,19 +94,28 @@ pub fn check_jar_protocol<P: AsRef<Path>>(path: P) -> Result<(), ExitValue> {
 }
 
 pub fn check_protocol(sock: &MessageSocket) -> Result<(), ExitValue> {
+    request_protocol(sock)?;
+
+    return Ok(());
+}
+
+/// Asks the server for its protocol version, failing if it isn't the one paperd uses.
+pub fn request_protocol(sock: &MessageSocket) -> Result<(), PaperError> {
     let message = ProtocolVersionMessage {};
     sock.send_message(&message)?;
 
-    let res = sock.receive_message::<ProtocolVersionMessageResponse>()?;
+    let res = sock.receive_message_timeout::<ProtocolVersionMessageResponse>(RESPONSE_TIMEOUT)?;
+    debug!(
+        "protocol.check",
+        server = res.protocol_version,
+        paperd = PROTOCOL_VERSION
+    );
 
     if res.protocol_version != PROTOCOL_VERSION {
-        eprintln!(
-            "The protocol versions of paperd and the specified server do not match. \
-             paperd protocol version: {}; server protocol version: {}. Please use a version \
-             of paperd compatible with this build of Paper.",
-            PROTOCOL_VERSION, res.protocol_version
-        );
-        return Err(ExitValue::Code(1));
+        return Err(PaperError::Protocol {
+            server: res.protocol_version,
+            paperd: PROTOCOL_VERSION,
+        });
     }
 
     return Ok(());
@@ -13,28 +13,314 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::log::{find_log_file, tail};
+use crate::client::Client;
+use crate::info;
+use crate::log::{find_log_file, follow_server, tail_lines, LogFormat};
+use crate::messaging::MessageSocket;
+use crate::output::progress;
 use crate::protocol::check_protocol;
-use crate::util::{get_sock, ExitValue};
+use crate::runner::{read_launch_state, relaunch, JavaEnv, LAUNCH_FILE_NAME};
+use crate::send::send_command;
+use crate::util::{
+    find_pid_file, find_sock_file, get_sock_from_file, get_sock_from_file_direct, parse_duration,
+    server_state, ExitError, ExitValue, ServerState,
+};
 use clap::ArgMatches;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::unistd::Pid;
+use paperd_lib::Socket;
 use serde::Serialize;
+use signal_hook::iterator::Signals;
+use signal_hook::SIGINT;
+use std::iter::once;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Long enough for the server to shut down and load the worlds again
+const RESTART_TIMEOUT: Duration = Duration::from_secs(120);
+
+// How many seconds before a scheduled restart players are warned, on top of a warning when the
+// restart is scheduled
+const WARNING_TIMES: [u64; 11] = [600, 300, 120, 60, 30, 10, 5, 4, 3, 2, 1];
 
 pub fn restart(sub_m: &ArgMatches) -> Result<(), ExitValue> {
-    let (sock, sock_file) = get_sock(sub_m)?;
+    let delay = match sub_m.value_of("DELAY") {
+        Some(text) => parse_duration(text).conv("Failed to parse --in")?,
+        None => Duration::from_secs(0),
+    };
+    let context = tail_lines(sub_m)?;
+
+    let sock_file = find_sock_file(sub_m)?;
+
+    // Servers started by `paperd start` or `paperd run` have a paperd instance waiting to start
+    // them again when they exit with the restart exit code
+    let daemon_pid = match find_pid_file(&sock_file).map(server_state) {
+        Some(ServerState::Running { pid }) => Some(pid),
+        _ => None,
+    };
+    let is_daemon = daemon_pid.is_some();
+
+    // Otherwise the server can still be started again if we know how it was launched. This has to
+    // be read now, it's removed when the server exits
+    let launch_state = if is_daemon {
+        None
+    } else {
+        sock_file
+            .parent()
+            .and_then(|dir| read_launch_state(&dir.join(LAUNCH_FILE_NAME)).ok())
+    };
+
+    if sub_m.is_present("DRY_RUN") {
+        print_plan(sub_m, &sock_file, daemon_pid, launch_state.as_ref(), delay);
+        return Ok(());
+    }
+
+    let sock = get_sock_from_file(&sock_file)?;
     check_protocol(&sock)?;
 
+    if delay > Duration::from_secs(0) {
+        countdown(&sock, delay)?;
+    }
+
+    // A quick restart can come and go between two checks of the socket, so the restart is noticed
+    // from the server's process and uptime instead, which change with every run of the server
+    let before = if is_daemon {
+        server_run(&sock_file)
+    } else {
+        None
+    };
+
     let message = RestartMessage {};
 
-    println!("Sending restart request...");
+    info!("Sending restart request...");
 
     sock.send_message(&message)?;
+    drop(sock);
+
+    if let Some(env) = launch_state {
+        wait_for_stop(&sock_file)?;
+        relaunch(env)?;
+    }
+
+    if sub_m.is_present("TAIL") {
+        let log_file = find_log_file(sub_m, &sock_file)?;
+        return follow_server(log_file, context, &sock_file, LogFormat::Plain, None);
+    }
+
+    if !is_daemon {
+        return Ok(());
+    }
+
+    // The paperd instance which started the server re-runs the saved launch state when the server
+    // exits with the restart exit code, so the server will come back up on its own, we just need to
+    // wait for it
+    return wait_for_restart(&sock_file, before);
+}
+
+/// Prints what would be done for `--dry-run`, without doing any of it.
+fn print_plan(
+    sub_m: &ArgMatches,
+    sock_file: &Path,
+    daemon_pid: Option<Pid>,
+    launch_state: Option<&JavaEnv>,
+    delay: Duration,
+) {
+    println!("Socket file: {}", sock_file.display());
+    if let (Some(pid_file), Some(pid)) = (find_pid_file(sock_file), daemon_pid) {
+        println!("PID file:    {}", pid_file.display());
+        println!("PID:         {}", pid);
+    }
+    println!("Actions:");
 
+    if delay > Duration::from_secs(0) {
+        println!(
+            "    Warn the players and wait {} before restarting",
+            format_remaining(delay.as_secs())
+        );
+    }
+    println!("    Send a restart request to the server through the socket file");
+    if daemon_pid.is_some() {
+        println!("    The paperd process which started the server starts it again once it exits");
+    } else if let Some(env) = launch_state {
+        println!(
+            "    Wait for the server to stop, then start it again in {} from its saved launch \
+             state",
+            env.working_dir().display()
+        );
+    } else {
+        println!(
+            "    Nothing starts the server again, it wasn't started by paperd and has no saved \
+             launch state"
+        );
+    }
     if sub_m.is_present("TAIL") {
-        let log_file = find_log_file(&sock_file)?;
-        return tail(log_file, 0, true);
+        println!("    Follow the server log");
+    }
+}
+
+/// Warns the players on the server that it's about to restart, at the times in `WARNING_TIMES`,
+/// and returns once `delay` has passed. Pressing C-c cancels the restart.
+fn countdown(sock: &MessageSocket, delay: Duration) -> Result<(), ExitValue> {
+    let end = match Instant::now().checked_add(delay) {
+        Some(end) => end,
+        None => {
+            eprintln!("The restart delay is too long");
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    let signals = Signals::new(&[SIGINT]).conv("Failed to register signal handlers")?;
+    let total = delay.as_secs();
+    let warnings = once(total).chain(WARNING_TIMES.iter().copied().filter(|t| *t < total));
+
+    info!(
+        "Restarting the server in {}, press C-c to cancel",
+        format_remaining(total)
+    );
+
+    let mut result = Ok(());
+    for remaining in warnings.chain(once(0)) {
+        let at = end - Duration::from_secs(remaining);
+        if !wait_until(at, &signals) {
+            info!("Restart cancelled");
+            result = send_command(sock, "say Restart cancelled").and(Err(ExitValue::Code(1)));
+            break;
+        }
+        if remaining == 0 {
+            break;
+        }
+
+        let command = format!("say Restarting in {}", format_remaining(remaining));
+        if let Err(e) = send_command(sock, command.as_str()) {
+            result = Err(e);
+            break;
+        }
+    }
+
+    signals.close();
+    return result;
+}
+
+/// Sleeps until `deadline`, returning `false` if C-c is pressed before then.
+fn wait_until(deadline: Instant, signals: &Signals) -> bool {
+    while Instant::now() < deadline {
+        if signals.pending().next().is_some() {
+            return false;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        sleep(left.min(Duration::from_millis(100)));
+    }
+    return signals.pending().next().is_none();
+}
+
+fn format_remaining(seconds: u64) -> String {
+    let (amount, unit) = if seconds >= 3600 && seconds % 3600 == 0 {
+        (seconds / 3600, "hour")
+    } else if seconds >= 60 && seconds % 60 == 0 {
+        (seconds / 60, "minute")
+    } else {
+        (seconds, "second")
+    };
+
+    return if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
+    };
+}
+
+fn wait_for_stop(sock_file: &Path) -> Result<(), ExitValue> {
+    progress("Waiting for server to stop.");
+
+    let start = Instant::now();
+    while start.elapsed() < RESTART_TIMEOUT {
+        if get_sock_from_file_direct(sock_file).is_err() {
+            info!();
+            return Ok(());
+        }
+
+        sleep(Duration::from_millis(500));
+        progress(".");
+    }
+    info!();
+
+    eprintln!("Timeout while waiting for server to stop");
+    return Err(ExitValue::Code(1));
+}
+
+/// Waits for the server to come back up after it was asked to restart. The server has restarted once
+/// it's a different run of the server than `before`, or once it's reachable again after it was seen
+/// to be down.
+fn wait_for_restart(sock_file: &Path, before: Option<ServerRun>) -> Result<(), ExitValue> {
+    progress("Waiting for server to restart.");
+
+    let start = Instant::now();
+    let mut stopped = false;
+    while start.elapsed() < RESTART_TIMEOUT {
+        let restarted = match server_run(sock_file) {
+            Some(run) => stopped || before.as_ref().map_or(false, |b| run.is_after(b)),
+            None => {
+                stopped = true;
+                false
+            }
+        };
+        if restarted {
+            info!();
+            info!("Server restarted");
+            return Ok(());
+        }
+
+        sleep(Duration::from_millis(500));
+        progress(".");
     }
+    info!();
+
+    eprintln!("Timeout while waiting for server to restart");
+    return Err(ExitValue::Code(1));
+}
+
+/// Tells one run of the server apart from the next. Either may be unknown, not every server reports
+/// its uptime, and the server's process is only known on Linux.
+struct ServerRun {
+    pid: Option<i32>,
+    uptime: Option<u64>,
+}
+
+impl ServerRun {
+    /// Whether this is a later run of the server than `before`.
+    fn is_after(&self, before: &ServerRun) -> bool {
+        let new_process = match (self.pid, before.pid) {
+            (Some(pid), Some(before)) => pid != before,
+            _ => false,
+        };
+        let uptime_reset = match (self.uptime, before.uptime) {
+            (Some(uptime), Some(before)) => uptime < before,
+            _ => false,
+        };
+        return new_process || uptime_reset;
+    }
+}
+
+/// Which run of the server is listening on the socket file, or `None` if it can't be reached.
+fn server_run(sock_file: &Path) -> Option<ServerRun> {
+    let client = Client::connect(sock_file).ok()?;
+    let status = client.status().ok()?;
+    return Some(ServerRun {
+        pid: peer_pid(client.socket().raw_fd()),
+        uptime: status.uptime,
+    });
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_pid(fd: Socket) -> Option<i32> {
+    return getsockopt(fd, PeerCredentials).ok().map(|cred| cred.pid());
+}
 
-    return Ok(());
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_pid(_fd: Socket) -> Option<i32> {
+    return None;
 }
 
 // Request
@@ -13,47 +13,187 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::client::Client;
+use crate::cmd::{
+    ARGS_FILE_CONFLICT, DEFAULT_ARGS_PERCENT_CONFLICT, GC_CONFLICT, JVM_ARGS_CONFLICT,
+    JVM_ARG_CONFLICT,
+};
 use crate::daemon::{run_daemon, Status};
-use crate::log::{find_log_file, tail};
+use crate::info;
+use crate::instance::register_instance;
+use crate::log::{default_log_file, tail, tail_from, wait_for_file};
+use crate::output::progress;
 use crate::protocol::check_jar_protocol;
-use crate::util::{find_program, ExitError, ExitValue};
+use crate::status::ServerPhase;
+use crate::stop::StopMessage;
+use crate::util::{
+    abstract_socket_name, find_program, get_sock_from_file_direct, local_tm, parse_duration,
+    ExitError, ExitValue,
+};
+use crate::verbose;
 use clap::ArgMatches;
 use nix::errno::Errno::ESRCH;
 use nix::sys::signal;
 use nix::sys::signal::kill;
-use nix::unistd::Pid;
+use nix::unistd::{access, geteuid, AccessFlags, Pid};
 use nix::Error;
-use serde::Deserialize;
+use paperd_lib::{ABSTRACT_SOCKET_VAR, SOCKET_MODE_VAR};
+use serde::{Deserialize, Serialize};
 use signal_hook::iterator::Signals;
 use signal_hook::{SIGABRT, SIGHUP, SIGINT, SIGQUIT, SIGTERM, SIGTRAP};
 use std::borrow::Cow;
 use std::cmp::{max, min};
+use std::collections::{BTreeMap, VecDeque};
 use std::convert::TryFrom;
 use std::fs::{canonicalize, File};
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::{Child, Command};
-use std::time::{Duration, Instant};
-use std::{env, fs, process, thread};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use std::{env, fs, io, process, thread};
 use sys_info::mem_info;
 
 static JNI_LIB: &'static [u8] = include_bytes!(env!("PAPERD_JNI_LIB"));
 
 pub const SOCK_FILE_NAME: &'static str = "paper.sock";
 pub const PID_FILE_NAME: &'static str = "paper.pid";
-
-const STOP_EXIT_CODE: i32 = 13;
-const RESTART_EXIT_CODE: i32 = 27;
+pub const LAUNCH_FILE_NAME: &'static str = "paper.launch.json";
+/// Written with the exit code paperd exits with once the server has stopped for good, for `paperd
+/// wait`, which can't wait on a process that isn't its child.
+pub const EXIT_FILE_NAME: &'static str = "paper.exit";
+/// Where the daemon logs the crashes it handles for `--restart-on-crash`.
+pub const CRASH_LOG_FILE_NAME: &'static str = "paper.crash.log";
+/// Where the daemon logs failures of the `--on-ready` and `--on-exit` commands, which it has no
+/// terminal to print to.
+pub const HOOK_LOG_FILE_NAME: &'static str = "paper.hooks.log";
+
+/// The exit code of a server which was asked to stop, paperd itself exits with 0 in that case.
+pub const STOP_EXIT_CODE: i32 = 13;
+/// The exit code of a server which was asked to restart, whichever paperd process is waiting on the
+/// server starts it again.
+pub const RESTART_EXIT_CODE: i32 = 27;
+
+// How long to wait for a newly started server to create its log file
+const LOG_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long `start --wait` waits for the server to finish starting by default
+const DEFAULT_WAIT_TIMEOUT: u64 = 300;
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// How often --stop-when-empty checks whether anyone is online
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+// --restart-on-crash gives up once the server has crashed this many times within the window. The
+// delay before starting it again doubles with each crash within the window
+const DEFAULT_CRASH_RETRIES: u32 = 5;
+const CRASH_WINDOW: Duration = Duration::from_secs(10 * 60);
+const CRASH_BACKOFF_START: Duration = Duration::from_secs(5);
+const CRASH_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// Current versions of Paper require Java 17
+const DEFAULT_MIN_JAVA: u32 = 17;
+
+// Bounds for the heap chosen by --default-args-percent. Servers struggle with less than 1G, and
+// above 31G the JVM can't use compressed object pointers, which wastes more memory than it gains
+const PERCENT_HEAP_MIN_MB: u64 = 1024;
+const PERCENT_HEAP_MAX_MB: u64 = 31 * 1024;
+
+// Which jars to prefer when --jar is a directory holding jars of more than one name
+const DEFAULT_JAR_NAME: &str = "paper";
 
 pub fn start(sub_m: &ArgMatches) -> Result<(), ExitValue> {
     let env = setup_java_env(sub_m)?;
 
     check_jar_protocol(&env.jar_file)?;
 
+    if sub_m.is_present("DRY_RUN") {
+        print_invocation(&env, true);
+        return Ok(());
+    }
+
+    check_root(sub_m)?;
+
+    let wait = if sub_m.is_present("WAIT") {
+        Some(Duration::from_secs(match sub_m.value_of("WAIT_TIMEOUT") {
+            Some(t) => t.parse::<u64>().conv("Failed to parse --wait-timeout")?,
+            None => DEFAULT_WAIT_TIMEOUT,
+        }))
+    } else {
+        None
+    };
+
+    if let Some(name) = sub_m.value_of("INSTANCE") {
+        register_instance(name, &env.working_dir)?;
+    }
+
     if !check_eula(&env)? {
-        return run_server_foreground(&env);
+        return run_server_foreground(env);
     }
 
+    let crash_policy = if sub_m.is_present("KEEP_ALIVE") {
+        CrashPolicy::Always
+    } else if sub_m.is_present("RESTART_ON_CRASH") {
+        CrashPolicy::Retry {
+            max_retries: match sub_m.value_of("RESTART_ON_CRASH") {
+                Some(n) => n
+                    .parse::<u32>()
+                    .conv("Failed to parse --restart-on-crash")?,
+                None => DEFAULT_CRASH_RETRIES,
+            },
+        }
+    } else {
+        CrashPolicy::Exit
+    };
+
+    let stop_when_empty = match sub_m.value_of("STOP_WHEN_EMPTY") {
+        Some(text) => Some(parse_duration(text).conv("Failed to parse --stop-when-empty")?),
+        None => None,
+    };
+
+    return start_daemon(
+        env,
+        crash_policy,
+        stop_when_empty,
+        sub_m.is_present("TAIL"),
+        wait,
+    );
+}
+
+/// What the daemon does when the server crashes, that is when it exits without being asked to
+/// stop or restart.
+#[derive(Clone, Copy)]
+enum CrashPolicy {
+    /// The server isn't started again, from `start` without `--keep-alive` or `--restart-on-crash`
+    Exit,
+    /// The server is always started again right away, from `--keep-alive`
+    Always,
+    /// The server is started again after a growing delay, until it has crashed `max_retries` times
+    /// within `CRASH_WINDOW`, from `--restart-on-crash`
+    Retry { max_retries: u32 },
+}
+
+/// Starts the server again from the launch state saved by the paperd instance which originally
+/// started it. Used by `restart` for servers which don't have a paperd instance waiting to restart
+/// them, such as servers started with `paperd run`.
+pub fn relaunch(env: JavaEnv) -> Result<(), ExitValue> {
+    check_jar_protocol(&env.jar_file)?;
+    return start_daemon(env, CrashPolicy::Exit, None, false, None);
+}
+
+fn start_daemon(
+    env: JavaEnv,
+    crash_policy: CrashPolicy,
+    stop_when_empty: Option<Duration>,
+    follow: bool,
+    wait: Option<Duration>,
+) -> Result<(), ExitValue> {
+    let launch_file = env.working_dir.join(LAUNCH_FILE_NAME);
+    write_launch_state(&launch_file, &env).conv("Failed to write launch state file")?;
+
     let mut lib_file = std::env::temp_dir();
     {
         lib_file.push("libpaperd_jni.so.gz");
@@ -71,9 +211,10 @@ pub fn start(sub_m: &ArgMatches) -> Result<(), ExitValue> {
         }
     }
 
+    let launched = SystemTime::now();
     match run_daemon() {
         Ok(Status::QUIT(pid)) => {
-            println!("Server starting in background, waiting for server to start...");
+            info!("Server starting in background, waiting for server to start...");
 
             let pid_file = env.working_dir.join(PID_FILE_NAME);
             let dur = Duration::from_secs(5);
@@ -85,10 +226,16 @@ pub fn start(sub_m: &ArgMatches) -> Result<(), ExitValue> {
             }
 
             return if pid_file.exists() {
-                println!("Server started in the background. PID: {}", pid);
-                if sub_m.is_present("TAIL") {
-                    let log_file = find_log_file(&pid_file)?;
-                    tail(log_file, 0, true)
+                info!("Server started in the background. PID: {}", pid);
+                if let Some(timeout) = wait {
+                    wait_for_running(&env.working_dir, Pid::from_raw(pid), timeout)
+                } else if follow {
+                    let log_file = default_log_file(&env.working_dir);
+                    info!(
+                        "Attaching to the server log, press C-c to detach. This will NOT stop \
+                         the server."
+                    );
+                    attach_log(&log_file, launched)
                 } else {
                     Ok(())
                 }
@@ -101,57 +248,149 @@ pub fn start(sub_m: &ArgMatches) -> Result<(), ExitValue> {
         Err(err) => return Err(err),
     }
 
+    // Don't keep whatever directory paperd happened to be started from in use, everything the
+    // daemon does from here on is relative to the server
+    if env::set_current_dir(&env.working_dir).is_err() {
+        return Err(ExitValue::Code(1));
+    }
+
+    let daemon_args = vec![
+        "-Dio.papermc.daemon.enabled=true".to_string(),
+        format!(
+            "-Dio.papermc.daemon.paperd.binary={}",
+            lib_file.to_string_lossy()
+        ),
+    ];
+
     let mut env = env;
-    env.args
-        .push("-Dio.papermc.daemon.enabled=true".to_string());
-    env.args.push(format!(
-        "-Dio.papermc.daemon.paperd.binary={}",
-        lib_file.to_string_lossy()
-    ));
+    let mut jar_modified = modified_time(&env.jar_file);
+
+    // Set once paperd itself has been asked to stop, the server must not be restarted after that
+    let stopping = Arc::new(AtomicBool::new(false));
+    let sock_file = env.working_dir.join(SOCK_FILE_NAME);
+    let exit_file = env.working_dir.join(EXIT_FILE_NAME);
+    let _ = fs::remove_file(&exit_file);
+    let crash_log = env.working_dir.join(CRASH_LOG_FILE_NAME);
+    let hook_log = env.working_dir.join(HOOK_LOG_FILE_NAME);
+
+    // When the server crashed recently, for --restart-on-crash
+    let mut crashes = VecDeque::<Instant>::new();
 
     let mut result: i32;
     loop {
-        let child = start_process(&env)?;
+        let child = start_process(&env, &daemon_args)?;
+        let child_pid = child.id();
 
         let pid = process::id();
 
         // Write pid file
         let pid_file = env.working_dir.join(PID_FILE_NAME);
         let pid_file = pid_file.as_path();
-        if let Err(_) = fs::write(pid_file, pid.to_string()) {
+        if let Err(_) = write_pid_file(pid_file, pid) {
             result = 1;
             break;
         }
 
-        let signals = forward_signals(pid)?;
+        // SIGTERM and SIGINT are what init systems use to stop us, handle those by stopping the
+        // server gracefully rather than passing the signal on
+        let stopping_bg = stopping.clone();
+        let sock_file_bg = sock_file.clone();
+        let signals = handle_signals(child_pid, move |sig| {
+            if sig != SIGTERM && sig != SIGINT {
+                return false;
+            }
+            stopping_bg.store(true, Ordering::SeqCst);
+            return request_stop(&sock_file_bg);
+        })?;
+
+        let child_exited = Arc::new(AtomicBool::new(false));
+        let idle_watch = stop_when_empty.map(|idle| {
+            return watch_idle(
+                sock_file.clone(),
+                idle,
+                stopping.clone(),
+                child_exited.clone(),
+            );
+        });
+        let ready_watch = env.on_ready.clone().map(|command| {
+            return watch_ready(
+                sock_file.clone(),
+                command,
+                env.working_dir.clone(),
+                child_pid,
+                hook_log.clone(),
+                child_exited.clone(),
+            );
+        });
 
         result = wait_for_child(child);
 
         signals.close();
+        child_exited.store(true, Ordering::SeqCst);
+        if let Some(idle_watch) = idle_watch {
+            let _ = idle_watch.join();
+        }
+        if let Some(ready_watch) = ready_watch {
+            let _ = ready_watch.join();
+        }
 
         let _ = fs::remove_file(pid_file);
 
-        // Check to see if we should restart from error
-        if sub_m.is_present("KEEP_ALIVE") {
-            if result == STOP_EXIT_CODE {
-                break;
-            } else {
-                // We need to restart, it looks like the server has crashed
-                continue;
+        // Check to see if we should restart from error. If the server didn't stop gracefully or
+        // to be restarted it looks like the server has crashed
+        let mut crash_delay = None;
+        let restart = if stopping.load(Ordering::SeqCst) || result == STOP_EXIT_CODE {
+            false
+        } else if result == RESTART_EXIT_CODE {
+            true
+        } else {
+            match crash_policy {
+                CrashPolicy::Exit => false,
+                CrashPolicy::Always => true,
+                CrashPolicy::Retry { max_retries } => {
+                    crash_delay = next_crash_delay(&mut crashes, max_retries);
+                    log_crash(&crash_log, result, crash_delay, crashes.len(), max_retries);
+                    crash_delay.is_some()
+                }
             }
-        }
+        };
 
-        if result != RESTART_EXIT_CODE {
+        // This runs before the server is started again, so backups and the like don't race with it
+        run_exit_hook(&env, result, restart, &hook_log);
+
+        if !restart {
             break;
         }
+
+        if let Some(delay) = crash_delay {
+            // Keep the PID file around while waiting, so `paperd stop` can still find us and stop
+            // the server from coming back
+            let _ = write_pid_file(pid_file, pid);
+            let interrupted = !wait_before_restart(delay);
+            let _ = fs::remove_file(pid_file);
+            if interrupted {
+                break;
+            }
+        }
+
+        env = match reload_env(&launch_file, env, &mut jar_modified) {
+            Some(env) => env,
+            None => {
+                result = 1;
+                break;
+            }
+        };
     }
 
+    let _ = fs::remove_file(&launch_file);
+
     if result == STOP_EXIT_CODE {
         // This signifies a successful exit
         // But being non-zero that would look like an error to most other things
         // So paperd won't return that
         result = 0;
     }
+    let _ = write_exit_file(&exit_file, result);
 
     // Attempt to cleanup a little
     if lib_file.exists() {
@@ -171,6 +410,431 @@ pub fn start(sub_m: &ArgMatches) -> Result<(), ExitValue> {
     };
 }
 
+/// Polls the status of a server which was just started until it has finished starting. `pid` is
+/// the daemon process, if it exits before the server is running the server failed to start. Servers
+/// which don't report their phase count as running once they respond to a status request.
+fn wait_for_running(working_dir: &Path, pid: Pid, timeout: Duration) -> Result<(), ExitValue> {
+    let sock_file = working_dir.join(SOCK_FILE_NAME);
+
+    let start = Instant::now();
+    progress("Waiting for the server to finish starting.");
+    while start.elapsed() < timeout {
+        // The daemon isn't our child, so its exit code isn't available, only whether it's running
+        if let Err(Error::Sys(ESRCH)) = kill(pid, None) {
+            info!();
+            eprintln!("The server exited while starting, check the server log for details");
+            return Err(ExitValue::Code(1));
+        }
+
+        let phase = Client::connect(&sock_file)
+            .and_then(|client| client.status())
+            .map(|status| status.phase());
+        if let Ok(None) | Ok(Some(ServerPhase::Running)) = phase {
+            info!();
+            info!(
+                "Server finished starting after {:.1} seconds",
+                start.elapsed().as_secs_f64()
+            );
+            return Ok(());
+        }
+
+        thread::sleep(WAIT_POLL_INTERVAL);
+        progress(".");
+    }
+    info!();
+
+    eprintln!(
+        "The server didn't finish starting within {} seconds",
+        timeout.as_secs()
+    );
+    return Err(ExitValue::Code(1));
+}
+
+/// Follows the log of a server which was just started. The server replaces the previous log file
+/// when it starts, if that has already happened the new log is shown from the beginning so none of
+/// the startup output is missed.
+fn attach_log(log_file: &Path, launched: SystemTime) -> Result<(), ExitValue> {
+    wait_for_file(log_file, LOG_WAIT_TIMEOUT);
+
+    return match modified_time(log_file) {
+        Some(modified) if modified >= launched => tail_from(log_file, 0, 0),
+        _ => tail(log_file, 0, true),
+    };
+}
+
+/// Runs the --on-exit command after the server exits, and waits for it to finish. The command is
+/// run with `sh` in the server's working directory, with the server's exit code and whether the
+/// server is about to be started again passed as environment variables. Failures are logged to
+/// `hook_log`.
+fn run_exit_hook(env: &JavaEnv, exit_code: i32, restarting: bool, hook_log: &Path) {
+    let command = match &env.on_exit {
+        Some(command) => command,
+        None => return,
+    };
+
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("PAPERD_EXIT_CODE", exit_code.to_string())
+        .env("PAPERD_RESTARTING", if restarting { "1" } else { "0" })
+        .current_dir(&env.working_dir)
+        .status();
+
+    let message = match result {
+        Ok(status) if !status.success() => format!("The --on-exit command failed: {}", status),
+        Err(e) => format!("Failed to run the --on-exit command: {}", e),
+        Ok(_) => return,
+    };
+    append_log(hook_log, &message);
+}
+
+/// Stops the server once nobody has been online for `idle`, for `--stop-when-empty`. The server is
+/// polled with status requests until `child_exited` is set. It only counts as empty once it has
+/// finished starting, so a server which takes a while to load its worlds isn't stopped before
+/// anyone had the chance to join.
+fn watch_idle(
+    sock_file: PathBuf,
+    idle: Duration,
+    stopping: Arc<AtomicBool>,
+    child_exited: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
+        let mut empty_since: Option<Instant> = None;
+        let mut next_poll = Instant::now() + IDLE_POLL_INTERVAL;
+
+        while !child_exited.load(Ordering::SeqCst) {
+            if Instant::now() < next_poll {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            next_poll = Instant::now() + IDLE_POLL_INTERVAL;
+
+            // A server which can't be reached, such as one which is still starting, isn't empty
+            let empty = match Client::connect(&sock_file).and_then(|client| client.status()) {
+                Ok(status) => match status.phase() {
+                    None | Some(ServerPhase::Running) => status.player_count() == 0,
+                    Some(ServerPhase::Starting) | Some(ServerPhase::Stopping) => false,
+                },
+                Err(_) => false,
+            };
+            if !empty {
+                empty_since = None;
+                continue;
+            }
+
+            let since = *empty_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= idle {
+                // The server was stopped on purpose, it mustn't be started again
+                stopping.store(true, Ordering::SeqCst);
+                request_stop(&sock_file);
+                return;
+            }
+        }
+    });
+}
+
+/// Runs the --on-ready command once the server has finished starting. The server is polled with
+/// status requests the same way as for `start --wait`, until it's running or `child_exited` is
+/// set. The command is run with `sh` in the server's working directory, with the PID of the
+/// server's Java process passed as an environment variable. It runs on its own thread, so the
+/// server isn't held up by it, and a failure is only logged to `hook_log`.
+fn watch_ready(
+    sock_file: PathBuf,
+    command: String,
+    working_dir: PathBuf,
+    child_pid: u32,
+    hook_log: PathBuf,
+    child_exited: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    return thread::spawn(move || {
+        loop {
+            if child_exited.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let phase = Client::connect(&sock_file)
+                .and_then(|client| client.status())
+                .map(|status| status.phase());
+            if let Ok(None) | Ok(Some(ServerPhase::Running)) = phase {
+                break;
+            }
+
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+
+        let result = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("PAPERD_PID", child_pid.to_string())
+            .current_dir(&working_dir)
+            .status();
+
+        let message = match result {
+            Ok(status) if !status.success() => {
+                format!("The --on-ready command failed: {}", status)
+            }
+            Err(e) => format!("Failed to run the --on-ready command: {}", e),
+            Ok(_) => return,
+        };
+        append_log(&hook_log, &message);
+    });
+}
+
+/// Decides how long to wait before starting the server again after it crashed, or returns `None`
+/// if it has crashed too often recently and shouldn't be started again. The delay doubles with each
+/// recent crash, so a server which crashes right away doesn't end up in a tight crash loop.
+fn next_crash_delay(crashes: &mut VecDeque<Instant>, max_retries: u32) -> Option<Duration> {
+    let now = Instant::now();
+    crashes.retain(|time| now.duration_since(*time) < CRASH_WINDOW);
+    if crashes.len() >= max_retries as usize {
+        return None;
+    }
+
+    let delay = CRASH_BACKOFF_START * 2u32.saturating_pow(crashes.len() as u32);
+    crashes.push_back(now);
+    return Some(min(delay, CRASH_BACKOFF_MAX));
+}
+
+/// The daemon has no terminal to print to, so crashes are logged to a file in the working
+/// directory instead. Failing to write it isn't a reason to not restart the server.
+fn log_crash(
+    crash_log: &Path,
+    exit_code: i32,
+    delay: Option<Duration>,
+    crashes: usize,
+    max_retries: u32,
+) {
+    let message = match delay {
+        Some(delay) => format!(
+            "Server crashed with exit code {}, starting it again in {} seconds (crash {} of at \
+             most {} within {} minutes)",
+            exit_code,
+            delay.as_secs(),
+            crashes,
+            max_retries,
+            CRASH_WINDOW.as_secs() / 60
+        ),
+        None => format!(
+            "Server crashed with exit code {}, it has crashed {} times within {} minutes, not \
+             starting it again",
+            exit_code,
+            crashes,
+            CRASH_WINDOW.as_secs() / 60
+        ),
+    };
+
+    append_log(crash_log, &message);
+}
+
+/// Appends a timestamped line to one of the daemon's log files. Failing to write it isn't worth
+/// failing over, the daemon has nowhere else to report it anyways.
+fn append_log(log_file: &Path, message: &str) {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file);
+    if let Ok(mut file) = file {
+        let _ = writeln!(file, "[{}] {}", local_timestamp(), message);
+    }
+}
+
+/// The current local date and time, as `2020-05-17 14:30:00`.
+pub fn local_timestamp() -> String {
+    let tm = local_tm();
+    return format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    );
+}
+
+/// Sleeps before the server is started again after a crash. Returns `false` if paperd is asked to
+/// stop in the meantime, in which case the server stays stopped.
+fn wait_before_restart(delay: Duration) -> bool {
+    let signals = match Signals::new(&[SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(_) => {
+            thread::sleep(delay);
+            return true;
+        }
+    };
+
+    let end = Instant::now() + delay;
+    let mut stopped = false;
+    while Instant::now() < end {
+        if signals.pending().next().is_some() {
+            stopped = true;
+            break;
+        }
+        thread::sleep(min(end - Instant::now(), Duration::from_millis(100)));
+    }
+
+    signals.close();
+    return !stopped;
+}
+
+/// Reads the launch state to restart the server with, so restarts always use the original command
+/// line. If the jar was picked from a directory, a newer jar of the same name dropped into it since
+/// is used instead.
+/// Returns `None` if the jar has been replaced with a build which isn't compatible.
+fn reload_env(
+    launch_file: &Path,
+    env: JavaEnv,
+    jar_modified: &mut Option<SystemTime>,
+) -> Option<JavaEnv> {
+    let mut env = read_launch_state(launch_file).unwrap_or(env);
+
+    let name = jar_name(&env.jar_file);
+    if let Some(Ok(Some(jar))) = env.jar_dir.as_ref().map(|dir| newest_jar(dir, Some(&name))) {
+        if jar != env.jar_file {
+            info!(
+                "Found newer jar {}, using it instead of {}",
+                jar.display(),
+                env.jar_file.display()
+            );
+            env.jar_file = jar;
+            let _ = write_launch_state(launch_file, &env);
+        }
+    }
+
+    let modified = modified_time(&env.jar_file);
+    if modified != *jar_modified {
+        if check_jar_protocol(&env.jar_file).is_err() {
+            return None;
+        }
+        *jar_modified = modified;
+    }
+
+    return Some(env);
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    return fs::metadata(path).and_then(|m| m.modified()).ok();
+}
+
+/// Picks the jar to run from a directory of versioned jars, such as `paper-1.20.4-123.jar`. Only
+/// jars with the given name are compared, the part of the file name in front of the version. The
+/// jar with the highest version numbers in its name wins, or the most recently modified one if
+/// those are the same.
+///
+/// Without a name, Paper jars are picked if there are any, otherwise the directory must only hold
+/// jars of one name.
+fn newest_jar(dir: &Path, name: Option<&str>) -> io::Result<Option<PathBuf>> {
+    let mut jars = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().map_or(false, |ext| ext == "jar") {
+            jars.push((jar_name(&path), path));
+        }
+    }
+
+    let name = match name {
+        Some(name) => name.to_string(),
+        None if jars.iter().any(|(n, _)| n == DEFAULT_JAR_NAME) => DEFAULT_JAR_NAME.to_string(),
+        None => {
+            let mut names: Vec<&str> = jars.iter().map(|(n, _)| n.as_str()).collect();
+            names.sort();
+            names.dedup();
+            match names.as_slice() {
+                [] => return Ok(None),
+                [name] => name.to_string(),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "it has jars named {}, point --jar at the one to run instead",
+                            names.join(", ")
+                        ),
+                    ));
+                }
+            }
+        }
+    };
+
+    let mut newest: Option<((Vec<u64>, Option<SystemTime>), PathBuf)> = None;
+    for (_, path) in jars.into_iter().filter(|(n, _)| *n == name) {
+        let key = (jar_version(&path), modified_time(&path));
+        if newest
+            .as_ref()
+            .map_or(true, |(newest_key, _)| key > *newest_key)
+        {
+            newest = Some((key, path));
+        }
+    }
+
+    return Ok(newest.map(|(_, path)| path));
+}
+
+/// The jar's file name up to its version, `paper-1.20.4-123.jar` is `paper`.
+fn jar_name(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let end = stem
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(stem.len());
+    return stem[..end]
+        .trim_end_matches(|c| c == '-' || c == '_' || c == '.')
+        .to_string();
+}
+
+/// The numbers in the jar's file name in order, `paper-1.20.4-123.jar` is `[1, 20, 4, 123]`.
+fn jar_version(path: &Path) -> Vec<u64> {
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    return name
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse::<u64>().ok())
+        .collect();
+}
+
+/// Saves everything needed to launch the server again, so restarts can reuse the original command
+/// line. This is written atomically for the same reason the PID file is.
+fn write_launch_state(launch_file: &Path, env: &JavaEnv) -> io::Result<()> {
+    let mut tmp_file = launch_file.as_os_str().to_os_string();
+    tmp_file.push(".tmp");
+    let tmp_file = PathBuf::from(tmp_file);
+
+    let data = serde_json::to_string(env)?;
+    fs::write(&tmp_file, data)?;
+    return fs::rename(&tmp_file, launch_file).map_err(|e| {
+        let _ = fs::remove_file(&tmp_file);
+        e
+    });
+}
+
+pub fn read_launch_state(launch_file: &Path) -> io::Result<JavaEnv> {
+    let data = fs::read_to_string(launch_file)?;
+    return Ok(serde_json::from_str(&data)?);
+}
+
+/// Writes the PID file atomically. Other commands delete PID files they can't parse, so they must
+/// never be able to observe a partially written file.
+fn write_pid_file(pid_file: &Path, pid: u32) -> io::Result<()> {
+    let mut tmp_file = pid_file.as_os_str().to_os_string();
+    tmp_file.push(".tmp");
+    let tmp_file = PathBuf::from(tmp_file);
+
+    fs::write(&tmp_file, pid.to_string())?;
+    return fs::rename(&tmp_file, pid_file).map_err(|e| {
+        let _ = fs::remove_file(&tmp_file);
+        e
+    });
+}
+
+/// The exit file is written before the PID file's process exits, so anything waiting for the
+/// process to exit can read it afterwards.
+fn write_exit_file(exit_file: &Path, exit_code: i32) -> io::Result<()> {
+    return fs::write(exit_file, exit_code.to_string());
+}
+
 fn check_eula(env: &JavaEnv) -> Result<bool, ExitValue> {
     // If this property is set then the eula is agreed by default
     for arg in &env.args {
@@ -182,7 +846,7 @@ fn check_eula(env: &JavaEnv) -> Result<bool, ExitValue> {
 
     let eula_path = env.working_dir.join("eula.txt");
     if !eula_path.exists() {
-        println!("eula.txt file not found, running server in foreground instead.");
+        info!("eula.txt file not found, running server in foreground instead.");
         return Ok(false);
     }
 
@@ -195,45 +859,221 @@ fn check_eula(env: &JavaEnv) -> Result<bool, ExitValue> {
         }
     }
 
-    println!("EULA not agreed to, running server in foreground instead.");
+    info!("EULA not agreed to, running server in foreground instead.");
     return Ok(false);
 }
 
 pub fn run_cmd(sub_m: &ArgMatches) -> Result<(), ExitValue> {
     let env = setup_java_env(sub_m)?;
-    return run_server_foreground(&env);
+    if sub_m.is_present("DRY_RUN") {
+        print_invocation(&env, false);
+        return Ok(());
+    }
+
+    check_root(sub_m)?;
+    return run_server_foreground(env);
+}
+
+/// Refuses to run the server as root unless --allow-root is passed. A compromised server, or a
+/// malicious plugin, would otherwise have full control over the machine.
+fn check_root(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    if !geteuid().is_root() {
+        return Ok(());
+    }
+
+    if sub_m.is_present("ALLOW_ROOT") {
+        verbose!("Running the server as root, as --allow-root was passed");
+        return Ok(());
+    }
+
+    eprintln!(
+        "Refusing to run the server as root. Anything the server or its plugins do would run with \
+         full control over this machine, run the server as an unprivileged user instead. If \
+         running as root is intended, for example in a container, pass --allow-root."
+    );
+    return Err(ExitValue::Code(1));
 }
 
-fn run_server_foreground(env: &JavaEnv) -> Result<(), ExitValue> {
-    let child = start_process(env)?;
+/// Runs the server attached to the terminal. paperd waits for the server to exit and exits with
+/// the same exit code, unless the server exits to be restarted, in which case it's started again.
+fn run_server_foreground(env: JavaEnv) -> Result<(), ExitValue> {
+    let launch_file = env.working_dir.join(LAUNCH_FILE_NAME);
+    write_launch_state(&launch_file, &env).conv("Failed to write launch state file")?;
+
+    // Commands find the paperd process waiting on the server through the PID file, same as for
+    // servers started in the background. `restart` needs this to know the server will be started
+    // again without its help
+    let pid_file = env.working_dir.join(PID_FILE_NAME);
+    write_pid_file(&pid_file, process::id()).conv("Failed to write PID file")?;
+    let exit_file = env.working_dir.join(EXIT_FILE_NAME);
+    let _ = fs::remove_file(&exit_file);
+
+    let mut env = env;
+    let mut jar_modified = modified_time(&env.jar_file);
 
-    let pid = child.id();
+    let mut result: i32;
+    loop {
+        let child = match start_process(&env, &[]) {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = fs::remove_file(&pid_file);
+                let _ = fs::remove_file(&launch_file);
+                return Err(e);
+            }
+        };
 
-    let signals = forward_signals(pid)?;
+        let pid = child.id();
 
-    let result = wait_for_child(child);
+        let signals = forward_signals(pid)?;
 
-    signals.close();
+        result = wait_for_child(child);
+
+        signals.close();
+
+        if result != RESTART_EXIT_CODE {
+            break;
+        }
+
+        info!("Server exited to restart, starting it again");
+        env = match reload_env(&launch_file, env, &mut jar_modified) {
+            Some(env) => env,
+            None => {
+                result = 1;
+                break;
+            }
+        };
+    }
+
+    let _ = fs::remove_file(&pid_file);
+    let _ = fs::remove_file(&launch_file);
+
+    if result == STOP_EXIT_CODE {
+        result = 0;
+    }
+    let _ = write_exit_file(&exit_file, result);
 
     return Err(ExitValue::Code(result));
 }
 
-struct JavaEnv {
+#[derive(Serialize, Deserialize)]
+pub struct JavaEnv {
+    #[serde(rename = "javaFile")]
     java_file: PathBuf,
+    #[serde(rename = "jarFile")]
     jar_file: PathBuf,
+    /// Set when the jar was picked from a directory, the newest jar in it is picked again for each
+    /// restart
+    #[serde(rename = "jarDir", default)]
+    jar_dir: Option<PathBuf>,
+    #[serde(rename = "workingDir")]
     working_dir: PathBuf,
+    #[serde(rename = "jvmArgs")]
     args: Vec<String>,
+    #[serde(rename = "serverArgs")]
     cmd_args: Vec<String>,
+    #[serde(rename = "envVars", default)]
+    env_vars: BTreeMap<String, String>,
+    #[serde(rename = "onExit", default)]
+    on_exit: Option<String>,
+    #[serde(rename = "onReady", default)]
+    on_ready: Option<String>,
+    #[serde(rename = "abstractSocket", default)]
+    abstract_socket: bool,
+    #[serde(rename = "socketMode", default)]
+    socket_mode: Option<u32>,
 }
 
-fn start_process(env: &JavaEnv) -> Result<Child, ExitValue> {
-    let result = Command::new(&env.java_file)
-        .args(&env.args)
-        .arg("-jar")
-        .arg(&env.jar_file)
-        .args(&env.cmd_args)
-        .current_dir(&env.working_dir)
-        .spawn();
+impl JavaEnv {
+    pub fn working_dir(&self) -> &Path {
+        return &self.working_dir;
+    }
+}
+
+/// What to do when the working directory already has a PID file.
+#[derive(Clone, Copy, PartialEq)]
+enum PidWriteMode {
+    /// Refuse to start the server.
+    Fail,
+    /// Replace the PID file, whether or not its process is still running.
+    Overwrite,
+    /// Replace the PID file only if its process isn't running anymore.
+    ReuseIfDead,
+}
+
+/// Removes an existing PID file in the server's working directory so it can be written again, or
+/// fails if `mode` doesn't allow it to be replaced.
+fn check_pid_file(pid_file: &Path, mode: PidWriteMode) -> Result<(), ExitValue> {
+    if !pid_file.is_file() {
+        return Ok(());
+    }
+
+    match mode {
+        PidWriteMode::Fail => {
+            eprintln!(
+                "Found existing PID file {}, will not continue",
+                pid_file.to_string_lossy()
+            );
+            return Err(ExitValue::Code(1));
+        }
+        PidWriteMode::Overwrite => {
+            info!("Found existing PID file, replacing");
+            return fs::remove_file(pid_file).conv("Failed to delete PID file");
+        }
+        PidWriteMode::ReuseIfDead => {}
+    }
+
+    let pid = fs::read_to_string(pid_file).conv("Failed to read PID file")?;
+    let pid = Pid::from_raw(pid.trim().parse::<i32>().conv("Failed to parse PID file")?);
+
+    match kill(pid, None) {
+        Ok(()) => {
+            eprintln!(
+                "Found server already running in this directory with PID {}, will not continue",
+                pid
+            );
+            return Err(ExitValue::Code(1));
+        }
+        Err(Error::Sys(e)) => {
+            if e == ESRCH {
+                info!("Found stale PID file, removing");
+                fs::remove_file(pid_file).conv("Failed to delete PID file")?;
+            } else {
+                println!("Unknown error occurred (start): {}", e);
+                return Err(ExitValue::Code(1));
+            }
+        }
+        _ => {}
+    }
+
+    return Ok(());
+}
+
+/// `extra_args` are passed to the JVM after the args in `env`, they aren't part of the saved launch
+/// state.
+fn start_process(env: &JavaEnv, extra_args: &[String]) -> Result<Child, ExitValue> {
+    let args = java_args(env, extra_args);
+    verbose!(
+        "Running {} in {}",
+        command_line(&env.java_file, &args),
+        env.working_dir.to_string_lossy()
+    );
+    for (key, value) in &env.env_vars {
+        verbose!("Setting environment variable {}={}", key, value);
+    }
+    let mut command = Command::new(&env.java_file);
+    command
+        .args(&args)
+        .envs(&env.env_vars)
+        .current_dir(&env.working_dir);
+    if env.abstract_socket {
+        let name = abstract_socket_name(&env.working_dir.join(SOCK_FILE_NAME));
+        verbose!("Using abstract socket {}", name);
+        command.env(ABSTRACT_SOCKET_VAR, name);
+    }
+    if let Some(mode) = env.socket_mode {
+        command.env(SOCKET_MODE_VAR, format!("{:o}", mode));
+    }
+    let result = command.spawn();
 
     return match result {
         Ok(c) => Ok(c),
@@ -244,6 +1084,60 @@ fn start_process(env: &JavaEnv) -> Result<Child, ExitValue> {
     };
 }
 
+/// The full list of arguments passed to the JVM to run the server.
+fn java_args(env: &JavaEnv, extra_args: &[String]) -> Vec<String> {
+    let mut args = Vec::<String>::new();
+    args.extend(env.args.iter().cloned());
+    args.extend(extra_args.iter().cloned());
+    args.push("-jar".to_string());
+    args.push(env.jar_file.to_string_lossy().to_string());
+    args.extend(env.cmd_args.iter().cloned());
+    return args;
+}
+
+/// Formats a command so it can be copied and pasted into a shell.
+fn command_line(program: &Path, args: &[String]) -> String {
+    let mut line = shell_quote(&program.to_string_lossy());
+    for arg in args {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    return line;
+}
+
+fn shell_quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=+./:,@%".contains(c));
+    if is_safe {
+        return arg.to_string();
+    }
+    return format!("'{}'", arg.replace('\'', "'\\''"));
+}
+
+/// Prints what would be run for `--dry-run`, without running anything.
+fn print_invocation(env: &JavaEnv, daemon: bool) {
+    println!("JVM:               {}", env.java_file.to_string_lossy());
+    println!("Working directory: {}", env.working_dir.to_string_lossy());
+    println!("Jar:               {}", env.jar_file.to_string_lossy());
+    if !env.env_vars.is_empty() {
+        println!("Environment:");
+        for (key, value) in &env.env_vars {
+            println!("    {}={}", key, shell_quote(value));
+        }
+    }
+    println!("Command line:");
+    println!("    {}", command_line(&env.java_file, &java_args(env, &[])));
+    if daemon {
+        println!();
+        println!(
+            "When started in the background, arguments to enable the daemon and load paperd's \
+             native library are added before -jar."
+        );
+    }
+}
+
 fn shell_context(s: &str) -> Result<Option<Cow<'static, str>>, env::VarError> {
     match env::var(s) {
         Ok(value) => Ok(Some(value.into())),
@@ -253,6 +1147,8 @@ fn shell_context(s: &str) -> Result<Option<Cow<'static, str>>, env::VarError> {
 }
 
 fn setup_java_env(sub_m: &ArgMatches) -> Result<JavaEnv, ExitValue> {
+    check_jvm_args(sub_m)?;
+
     let mut config: Option<RunnerConfig> = match sub_m.value_of("CONFIG_FILE") {
         Some(config_path_text) => {
             let config_path = PathBuf::from(config_path_text);
@@ -303,55 +1199,109 @@ fn setup_java_env(sub_m: &ArgMatches) -> Result<JavaEnv, ExitValue> {
 
     let config = config.as_ref();
 
-    // Find Java executable
-    let java_path = config
-        .and_then(|c| c.jvm.as_ref().map(|s| s.as_str()))
-        .or(sub_m.value_of("JVM"))
-        .map(PathBuf::from)
-        .or_else(find_java);
-
-    let java_path = match java_path {
-        Some(path) => path,
-        None => {
-            eprintln!(
-                "Could not find a JVM executable. Either make sure it's present on the PATH, or \
-                 there's a valid JAVA_HOME, or specify it with -j. See --help for more details."
-            );
-            return Err(ExitValue::Code(1));
-        }
-    };
+    // An explicitly set working directory is resolved first, relative jar paths are resolved
+    // against it
+    let working_dir = config
+        .and_then(|c| c.working_dir.as_ref().map(|s| s.as_str()))
+        .or(sub_m.value_of("CWD"))
+        .map(|s| PathBuf::from(s));
 
-    // Find target jar file
+    // Find target jar file, or the directory to pick the newest jar from
+    let mut jar_dir = None;
     let jar_path = match config
         .and_then(|c| c.jar_file.as_ref().map(|s| s.as_str()))
         .or(sub_m.value_of("JAR"))
     {
-        Some(path) => match canonicalize(PathBuf::from(path)) {
-            Ok(canonical) => canonical,
-            Err(e) => {
-                eprintln!("Failed to get full path to jar {}: {}", path, e);
+        Some(path) => {
+            let path = match &working_dir {
+                Some(dir) => dir.join(path),
+                None => PathBuf::from(path),
+            };
+            if !path.exists() {
+                // The path can't be canonicalized if it doesn't exist, but the error should still
+                // show where paperd looked
+                let path = env::current_dir()
+                    .map(|dir| dir.join(&path))
+                    .unwrap_or(path);
+                eprintln!("jar not found: {}", path.to_string_lossy());
                 return Err(ExitValue::Code(1));
             }
-        },
+            let path = if path.is_dir() {
+                let dir = canonicalize(&path).conv(format!(
+                    "Failed to get full path to jar directory {}",
+                    path.to_string_lossy()
+                ))?;
+                let jar = match newest_jar(&dir, None) {
+                    Ok(Some(jar)) => jar,
+                    Ok(None) => {
+                        eprintln!("No jar found in {}", dir.to_string_lossy());
+                        return Err(ExitValue::Code(1));
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "Failed to search for a jar in {}: {}",
+                            dir.to_string_lossy(),
+                            e
+                        );
+                        return Err(ExitValue::Code(1));
+                    }
+                };
+                info!("Using newest jar in {}: {}", dir.display(), jar.display());
+                jar_dir = Some(dir);
+                jar
+            } else {
+                path
+            };
+            match canonicalize(&path) {
+                Ok(canonical) => canonical,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to get full path to jar {}: {}",
+                        path.to_string_lossy(),
+                        e
+                    );
+                    return Err(ExitValue::Code(1));
+                }
+            }
+        }
         None => {
             eprintln!("Failed to resolve jar file path");
             return Err(ExitValue::Code(1));
         }
     };
     if !jar_path.is_file() {
-        eprintln!("Could not find jar {}", jar_path.to_string_lossy());
+        eprintln!("jar is not a regular file: {}", jar_path.to_string_lossy());
+        return Err(ExitValue::Code(1));
+    }
+    if access(&jar_path, AccessFlags::R_OK).is_err() {
+        eprintln!("jar is not readable: {}", jar_path.to_string_lossy());
         return Err(ExitValue::Code(1));
     }
 
-    // Get the jar's parent directory
-    let parent_path = config
-        .and_then(|c| c.working_dir.as_ref().map(|s| s.as_str()))
-        .or(sub_m.value_of("CWD"))
-        .map(|s| PathBuf::from(s))
-        .or_else(|| jar_path.parent().map(|p| p.to_path_buf()));
+    // Find Java executable
+    let java_path = find_java(
+        sub_m
+            .value_of("JVM")
+            .or_else(|| config.and_then(|c| c.jvm.as_ref().map(|s| s.as_str()))),
+    )?;
+
+    let min_java = match sub_m.value_of("MIN_JAVA") {
+        Some(v) => v
+            .parse::<u32>()
+            .conv("Failed to parse --min-java argument")?,
+        None => config.and_then(|c| c.min_java).unwrap_or(DEFAULT_MIN_JAVA),
+    };
+    check_java_version(&java_path, min_java, sub_m.is_present("STRICT_JAVA"))?;
+
+    // Default to the jar's parent directory
+    let parent_path = working_dir.or_else(|| jar_path.parent().map(|p| p.to_path_buf()));
 
+    // The launch state is used from other directories, so this must be an absolute path
     let parent_path = match parent_path {
-        Some(path) => path,
+        Some(path) => canonicalize(&path).conv(format!(
+            "Failed to get full path to working directory {}",
+            path.to_string_lossy()
+        ))?,
         None => {
             eprintln!(
                 "Failed to find parent directory for jar {}",
@@ -361,33 +1311,34 @@ fn setup_java_env(sub_m: &ArgMatches) -> Result<JavaEnv, ExitValue> {
         }
     };
 
-    let pid_file = parent_path.join(PID_FILE_NAME);
-    if pid_file.is_file() {
-        let pid = fs::read_to_string(&pid_file).conv("Failed to read PID file")?;
-        let pid = Pid::from_raw(pid.parse::<i32>().conv("Failed to parse PID file")?);
-
-        match kill(pid, None) {
-            Ok(()) => {
-                eprintln!(
-                    "Found server already running in this directory with PID {}, will not continue",
-                    pid
-                );
-                return Err(ExitValue::Code(1));
-            }
-            Err(Error::Sys(e)) => {
-                if e == ESRCH {
-                    println!("Found stale PID file, removing");
-                    fs::remove_file(&pid_file).conv("Failed to delete PID file")?;
-                } else {
-                    println!("Unknown error occurred (start): {}", e);
-                    return Err(ExitValue::Code(1));
-                }
-            }
-            _ => {}
-        }
+    // The server writes its logs, worlds, and our PID file here
+    if !parent_path.is_dir() {
+        eprintln!(
+            "Working directory {} is not a directory",
+            parent_path.to_string_lossy()
+        );
+        return Err(ExitValue::Code(1));
+    }
+    if access(&parent_path, AccessFlags::W_OK).is_err() {
+        eprintln!(
+            "Working directory {} is not writable",
+            parent_path.to_string_lossy()
+        );
+        return Err(ExitValue::Code(1));
     }
 
-    let jvm_args = get_jvm_args(&config, sub_m)?;
+    let pid_write_mode = match sub_m.value_of("PID_WRITE_MODE") {
+        Some("fail") => PidWriteMode::Fail,
+        Some("overwrite") => PidWriteMode::Overwrite,
+        _ => PidWriteMode::ReuseIfDead,
+    };
+    check_pid_file(&parent_path.join(PID_FILE_NAME), pid_write_mode)?;
+
+    let mut jvm_args = get_jvm_args(&config, sub_m)?;
+    // Extra arguments come last, so they can override the defaults
+    if let Some(extra) = sub_m.values_of("JVM_ARG") {
+        jvm_args.extend(extra.map(|s| s.to_string()));
+    }
     let server_args = sub_m
         .values_of("SERVER_ARGS")
         .map(|values| values.map(|s| s.to_string()).collect())
@@ -397,17 +1348,97 @@ fn setup_java_env(sub_m: &ArgMatches) -> Result<JavaEnv, ExitValue> {
                 .unwrap_or_else(|| Vec::new())
         });
 
+    let mut env_vars = BTreeMap::new();
+    for var in sub_m.values_of("ENV").into_iter().flatten() {
+        let (key, value) = parse_env_var(var)?;
+        env_vars.insert(key, value);
+    }
+
+    let abstract_socket = sub_m.is_present("ABSTRACT_SOCKET");
+    if abstract_socket && !cfg!(any(target_os = "linux", target_os = "android")) {
+        eprintln!("--abstract-socket is only supported on Linux");
+        return Err(ExitValue::Code(1));
+    }
+
+    let socket_mode = match sub_m.value_of("SOCKET_MODE") {
+        Some(mode) => Some(parse_socket_mode(mode)?),
+        None => None,
+    };
+
     return Ok(JavaEnv {
         java_file: java_path,
         jar_file: jar_path,
+        jar_dir,
         working_dir: parent_path,
         args: jvm_args,
         cmd_args: server_args,
+        env_vars,
+        on_exit: sub_m.value_of("ON_EXIT").map(|s| s.to_string()),
+        on_ready: sub_m.value_of("ON_READY").map(|s| s.to_string()),
+        abstract_socket,
+        socket_mode,
     });
 }
 
+/// Parses the permissions of the socket file for `--socket-mode`, an octal number like `chmod`
+/// takes. Only the permission bits may be set.
+fn parse_socket_mode(mode: &str) -> Result<u32, ExitValue> {
+    let parsed = u32::from_str_radix(mode.trim_start_matches("0o"), 8).conv(format!(
+        "Invalid socket mode '{}', it must be an octal number such as 600",
+        mode
+    ))?;
+    if parsed > 0o777 {
+        eprintln!(
+            "Invalid socket mode '{}', only the permission bits (at most 777) may be set",
+            mode
+        );
+        return Err(ExitValue::Code(1));
+    }
+    if parsed & 0o002 != 0 {
+        eprintln!(
+            "Warning: socket mode {:03o} lets every user on this machine run commands on the server",
+            parsed
+        );
+    }
+    return Ok(parsed);
+}
+
+/// Splits a `KEY=VALUE` argument into the name and value of an environment variable. Only the first
+/// `=` separates the two, the value may contain more of them.
+fn parse_env_var(var: &str) -> Result<(String, String), ExitValue> {
+    let (key, value) = match var.find('=') {
+        Some(i) => (&var[..i], &var[i + 1..]),
+        None => {
+            eprintln!(
+                "Invalid environment variable '{}', it must be given as KEY=VALUE",
+                var
+            );
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    if key.is_empty() || key.contains('\0') || value.contains('\0') {
+        eprintln!(
+            "Invalid environment variable '{}', the name must not be empty and neither the name \
+             nor the value may contain NUL characters",
+            var
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    return Ok((key.to_string(), value.to_string()));
+}
+
 fn forward_signals(pid: u32) -> Result<Signals, ExitValue> {
-    // While the server is running we'll redirect some signals to it
+    return handle_signals(pid, |_| false);
+}
+
+/// While the server is running we'll redirect some signals to it. `handler` is given each signal
+/// first, if it returns `true` the signal has been dealt with and isn't sent to the server.
+fn handle_signals<F>(pid: u32, handler: F) -> Result<Signals, ExitValue>
+where
+    F: Fn(i32) -> bool + Send + 'static,
+{
     let signals = Signals::new(&[SIGHUP, SIGINT, SIGQUIT, SIGTRAP, SIGABRT, SIGTERM]);
     let signals = match signals {
         Ok(s) => s,
@@ -420,6 +1451,9 @@ fn forward_signals(pid: u32) -> Result<Signals, ExitValue> {
     let signals_bg = signals.clone();
     thread::spawn(move || {
         for sig_int in signals_bg.forever() {
+            if handler(sig_int) {
+                continue;
+            }
             if let Ok(sig) = signal::Signal::try_from(sig_int) {
                 let _ = signal::kill(Pid::from_raw(pid as i32), sig);
             }
@@ -429,8 +1463,25 @@ fn forward_signals(pid: u32) -> Result<Signals, ExitValue> {
     return Ok(signals);
 }
 
+/// Asks the server to stop the same way `paperd stop` does. Returns `false` if the server couldn't
+/// be reached, in which case the caller should fall back to signalling the server directly.
+fn request_stop(sock_file: &Path) -> bool {
+    return match get_sock_from_file_direct(sock_file) {
+        Ok(sock) => sock.send_message(&StopMessage {}).is_ok(),
+        Err(_) => false,
+    };
+}
+
+/// Waits for the server to exit, returning its exit code. If the server was killed by a signal the
+/// exit code is 128 plus the signal number, the same as shells report it.
 fn wait_for_child(mut child: Child) -> i32 {
-    return match child.wait().map(|status| status.code().unwrap_or(1)) {
+    let exit_code = |status: ExitStatus| {
+        return status
+            .code()
+            .or_else(|| status.signal().map(|sig| 128 + sig))
+            .unwrap_or(1);
+    };
+    return match child.wait().map(exit_code) {
         Ok(status) => status,
         Err(err) => {
             eprintln!("Error while running server: {}", err);
@@ -439,9 +1490,183 @@ fn wait_for_child(mut child: Child) -> i32 {
     };
 }
 
-/// Searches the PATH for java. If that fails, JAVA_HOME is searched as well.
-fn find_java() -> Option<PathBuf> {
-    return find_program(&[("PATH", "java"), ("JAVA_HOME", "bin/java")]);
+/// Resolves the java executable to use. An explicitly provided JVM is always used if present,
+/// otherwise the PATH is searched for java. If that fails, JAVA_HOME is searched as well.
+fn find_java(jvm_arg: Option<&str>) -> Result<PathBuf, ExitValue> {
+    let java_path = match jvm_arg {
+        Some(path) => PathBuf::from(path),
+        None => match find_program(&[("PATH", "java"), ("JAVA_HOME", "bin/java")]) {
+            Some(path) => path,
+            None => {
+                eprintln!(
+                    "Could not find a JVM executable. Either make sure it's present on the PATH, \
+                     or there's a valid JAVA_HOME, or specify it with --jvm. See --help for more \
+                     details."
+                );
+                return Err(ExitValue::Code(1));
+            }
+        },
+    };
+
+    let is_executable = fs::metadata(&java_path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false);
+    if !is_executable {
+        eprintln!(
+            "The JVM {} does not exist or is not executable",
+            java_path.to_string_lossy()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    verbose!("Using JVM {}", java_path.to_string_lossy());
+    return Ok(java_path);
+}
+
+/// Runs `java -version` to make sure the JVM is new enough, otherwise the server fails with an
+/// unhelpful class file version error. If the version can't be determined the JVM is given the
+/// benefit of the doubt.
+fn check_java_version(java_path: &Path, min_version: u32, strict: bool) -> Result<(), ExitValue> {
+    let output = match Command::new(java_path).arg("-version").output() {
+        Ok(o) => o,
+        Err(e) => {
+            verbose!(
+                "Failed to run {} -version: {}",
+                java_path.to_string_lossy(),
+                e
+            );
+            return Ok(());
+        }
+    };
+
+    // The version is printed to stderr
+    let text = String::from_utf8_lossy(&output.stderr);
+    let version = match parse_java_version(&text) {
+        Some(v) => v,
+        None => {
+            verbose!("Could not determine Java version from: {}", text.trim());
+            return Ok(());
+        }
+    };
+    verbose!("Found Java version {}", version);
+
+    if version >= min_version {
+        return Ok(());
+    }
+
+    if strict {
+        eprintln!(
+            "The JVM {} is Java {}, but at least Java {} is required to run this server.",
+            java_path.to_string_lossy(),
+            version,
+            min_version
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    info!(
+        "Warning: The JVM {} is Java {}, but at least Java {} is required to run this server. \
+         The server will most likely fail to start, use --jvm to select a newer JVM.",
+        java_path.to_string_lossy(),
+        version,
+        min_version
+    );
+    return Ok(());
+}
+
+/// Finds the major version in `java -version` output, which looks like one of:
+///
+/// ```text
+/// java version "1.8.0_292"
+/// openjdk version "17.0.1" 2021-10-19
+/// openjdk version "21" 2023-09-19
+/// ```
+///
+/// Before Java 9 the major version came after `1.`.
+fn parse_java_version(text: &str) -> Option<u32> {
+    let line = text.lines().find(|l| l.contains(" version \""))?;
+    let version = line.split('"').nth(1)?;
+
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse::<u32>().ok()?;
+    if major == 1 {
+        return parts.next()?.parse::<u32>().ok();
+    }
+    return Some(major);
+}
+
+/// Parses a JVM heap size such as 500m or 10G: a whole number of megabytes or gigabytes. The
+/// unit is case insensitive, the returned value is normalized to use a lower case unit so it can be
+/// passed directly to -Xms and -Xmx.
+fn parse_memory(value: &str) -> Result<String, ExitValue> {
+    let invalid = || {
+        eprintln!(
+            "Invalid format for JVM heap size: '{}'. It should be a whole number followed by m (for \
+             megabytes) or G (for gigabytes), something like 500m or 2G.",
+            value
+        );
+        return Err(ExitValue::Code(1));
+    };
+
+    let (amount, unit) = match value.char_indices().last() {
+        Some((i, c)) => (&value[..i], c.to_ascii_lowercase()),
+        None => return invalid(),
+    };
+
+    if unit != 'm' && unit != 'g' {
+        return invalid();
+    }
+    if amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit()) {
+        return invalid();
+    }
+
+    return match amount.parse::<u64>() {
+        Ok(0) | Err(_) => invalid(),
+        Ok(amount) => Ok(format!("{}{}", amount, unit)),
+    };
+}
+
+/// Reads JVM arguments from a file with one argument per line. Each line is a single argument,
+/// whitespace within a line is kept rather than splitting it into more arguments.
+fn read_args_file(file: &Path) -> Result<Vec<String>, ExitValue> {
+    let text = fs::read_to_string(file).conv(format!(
+        "Failed to read JVM arguments from {}",
+        file.display()
+    ))?;
+
+    return Ok(text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect());
+}
+
+/// Refuses ways of choosing JVM arguments which can't be used together. This is checked here
+/// rather than by clap, as clap's errors only name one side of the conflict and can't explain it.
+fn check_jvm_args(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let custom = sub_m.is_present("CUSTOM_ARGS") || sub_m.is_present("ARGS_FILE");
+
+    let conflict = if sub_m.is_present("DEFAULT_ARGS_PERCENT")
+        && (sub_m.is_present("DEFAULT_ARGS") || custom)
+    {
+        DEFAULT_ARGS_PERCENT_CONFLICT
+    } else if sub_m.is_present("JVM_ARG") && custom {
+        JVM_ARG_CONFLICT
+    } else if sub_m.is_present("ARGS_FILE")
+        && (sub_m.is_present("DEFAULT_ARGS") || sub_m.is_present("CUSTOM_ARGS"))
+    {
+        ARGS_FILE_CONFLICT
+    } else if sub_m.is_present("GC") && custom {
+        GC_CONFLICT
+    } else if sub_m.is_present("DEFAULT_ARGS") && sub_m.is_present("CUSTOM_ARGS") {
+        JVM_ARGS_CONFLICT
+    } else {
+        return Ok(());
+    };
+
+    eprintln!("{}", conflict);
+    return Err(ExitValue::Code(1));
 }
 
 fn get_jvm_args(
@@ -451,75 +1676,167 @@ fn get_jvm_args(
     if let Some(vals) = sub_m.values_of("CUSTOM_ARGS") {
         return Ok(vals.map(|s| s.to_string()).collect());
     }
-    if let Some(args) = config.and_then(|c| c.jvm_args.as_ref().map(|a| a.clone())) {
-        return Ok(args);
+
+    if let Some(file) = sub_m.value_of("ARGS_FILE") {
+        return read_args_file(Path::new(file));
+    }
+
+    let gc_flags = match sub_m.value_of("GC") {
+        Some("zgc") => zgc_flags,
+        Some("shenandoah") => shenandoah_flags,
+        _ => aikars_flags,
+    };
+
+    // Command line arguments take precedence over the config file, including --default-args
+    if let Some(value) = sub_m.value_of("DEFAULT_ARGS") {
+        return Ok(gc_flags(parse_memory(value)?.as_str()));
+    }
+    if let Some(value) = sub_m.value_of("DEFAULT_ARGS_PERCENT") {
+        return Ok(gc_flags(memory_from_percent(value)?.as_str()));
+    }
+
+    // Choosing a garbage collector asks for default flags as well
+    if !sub_m.is_present("GC") {
+        if let Some(args) = config.and_then(|c| c.jvm_args.as_ref().map(|a| a.clone())) {
+            return Ok(args);
+        }
     }
 
     // When all else fails, use 500m
     // This should hopefully be small enough to not cause problems for anyone
     let mut heap: String = "500m".to_string();
 
-    if let Some(value) = sub_m.value_of("DEFAULT_ARGS") {
-        const ERROR_MSG: &str =
-            "Invalid format for JVM heap size. Should be something like 500m or 2G.";
-        if value.is_empty() {
-            eprintln!("{}", ERROR_MSG);
+    // If no arguments are provided, use 1/2 of the current available memory with default flags
+    if let Ok(info) = mem_info() {
+        // info.avail should always be greater than free, but it seems there may be a bug
+        // for macOS. Assuming most users are using linux this doesn't really affect much
+        let mem = max(info.avail, info.free);
+        // mem is in kb, so convert to mb by dividing by 1000
+        // Then we take half of it
+        // Cap the amount we automatically choose at 10G
+        let mut mb = min((mem / 1000) / 2, 10000).to_string();
+
+        info!(
+            "Warning: No memory argument provided, automatically determining to use {} MB \
+             instead. This is not recommended, please specify an amount of memory with -d or \
+             --default-args",
+            mb
+        );
+
+        mb.push_str("m");
+        heap = mb;
+    }
+
+    return Ok(gc_flags(heap.as_str()));
+}
+
+/// Works out the heap size for `--default-args-percent` from the total memory of the system, in the
+/// same format as `parse_memory`. The result is clamped to `PERCENT_HEAP_MIN_MB` and
+/// `PERCENT_HEAP_MAX_MB`.
+fn memory_from_percent(value: &str) -> Result<String, ExitValue> {
+    let percent = match value.trim_end_matches('%').parse::<u64>() {
+        Ok(percent) if percent >= 1 && percent <= 100 => percent,
+        _ => {
+            eprintln!(
+                "Invalid value for --default-args-percent: '{}'. It should be a whole number from \
+                 1 to 100.",
+                value
+            );
             return Err(ExitValue::Code(1));
         }
+    };
 
-        if value[..value.len() - 1]
-            .chars()
-            .any(|c| !c.is_ascii_digit())
-            && value.chars().last().map_or(true, |c| c != 'm' && c != 'G')
-        {
-            eprintln!("{}", ERROR_MSG);
+    let total_mb = match mem_info() {
+        // total is in kb
+        Ok(info) => info.total / 1024,
+        Err(e) => {
+            eprintln!("Failed to read the amount of memory on this system: {}", e);
             return Err(ExitValue::Code(1));
         }
+    };
 
-        heap = value.to_string();
+    let wanted_mb = total_mb * percent / 100;
+    let mb = min(max(wanted_mb, PERCENT_HEAP_MIN_MB), PERCENT_HEAP_MAX_MB);
+    if mb == wanted_mb {
+        info!(
+            "Using {}% of the system's {} MB of memory: {} MB",
+            percent, total_mb, mb
+        );
     } else {
-        // If no arguments are provided, use 1/2 of the current available memory with default flags
-        if let Ok(info) = mem_info() {
-            // info.avail should always be greater than free, but it seems there may be a bug
-            // for macOS. Assuming most users are using linux this doesn't really affect much
-            let mem = max(info.avail, info.free);
-            // mem is in kb, so convert to mb by dividing by 1000
-            // Then we take half of it
-            // Cap the amount we automatically choose at 10G
-            let mut mb = min((mem / 1000) / 2, 10000).to_string();
-
-            println!(
-                "Warning: No memory argument provided, automatically determining to use {} MB \
-                 instead. This is not recommended, please specify an amount of memory with -d or \
-                 --default-args",
-                mb
-            );
-
-            mb.push_str("m");
-            heap = mb;
-        }
+        info!(
+            "{}% of the system's {} MB of memory is {} MB, using {} MB instead",
+            percent, total_mb, wanted_mb, mb
+        );
     }
 
-    let mut xms = "-Xms".to_string();
-    let mut xmx = "-Xmx".to_string();
-    xms.push_str(heap.as_str());
-    xmx.push_str(heap.as_str());
+    return Ok(format!("{}m", mb));
+}
+
+/// Aikar's recommended flags for running a server with the given heap size, which must be in the
+/// normalized format returned by `parse_memory`. These are kept in line with the flags recommended
+/// at https://mcflags.emc.gs, including the adjustments for heaps larger than 12G.
+fn aikars_flags(heap: &str) -> Vec<String> {
+    let (amount, unit) = heap.split_at(heap.len() - 1);
+    let heap_mb = amount.parse::<u64>().unwrap_or(0) * if unit == "g" { 1024 } else { 1 };
 
-    return Ok(vec![
-        xms,
-        xmx,
+    let (new_size, max_new_size, region_size, reserve, occupancy) = if heap_mb > 12 * 1024 {
+        (40, 50, "16M", 15, 20)
+    } else {
+        (30, 40, "8M", 20, 15)
+    };
+
+    return vec![
+        format!("-Xms{}", heap),
+        format!("-Xmx{}", heap),
         "-XX:+UseG1GC".to_string(),
+        "-XX:+ParallelRefProcEnabled".to_string(),
+        "-XX:MaxGCPauseMillis=200".to_string(),
         "-XX:+UnlockExperimentalVMOptions".to_string(),
-        "-XX:MaxGCPauseMillis=100".to_string(),
         "-XX:+DisableExplicitGC".to_string(),
-        "-XX:TargetSurvivorRatio=90".to_string(),
-        "-XX:G1NewSizePercent=50".to_string(),
-        "-XX:G1MaxNewSizePercent=80".to_string(),
-        "-XX:G1MixedGCLiveThresholdPercent=35".to_string(),
         "-XX:+AlwaysPreTouch".to_string(),
+        format!("-XX:G1NewSizePercent={}", new_size),
+        format!("-XX:G1MaxNewSizePercent={}", max_new_size),
+        format!("-XX:G1HeapRegionSize={}", region_size),
+        format!("-XX:G1ReservePercent={}", reserve),
+        "-XX:G1HeapWastePercent=5".to_string(),
+        "-XX:G1MixedGCCountTarget=4".to_string(),
+        format!("-XX:InitiatingHeapOccupancyPercent={}", occupancy),
+        "-XX:G1MixedGCLiveThresholdPercent=90".to_string(),
+        "-XX:G1RSetUpdatingPauseTimePercent=5".to_string(),
+        "-XX:SurvivorRatio=32".to_string(),
+        "-XX:+PerfDisableSharedMem".to_string(),
+        "-XX:MaxTenuringThreshold=1".to_string(),
+        "-Dusing.aikars.flags=https://mcflags.emc.gs".to_string(),
+        "-Daikars.new.flags=true".to_string(),
+    ];
+}
+
+/// Flags for running a server with ZGC, which keeps pauses very short even on large heaps, at the
+/// cost of some throughput. `heap` is in the same format as for `aikars_flags`.
+fn zgc_flags(heap: &str) -> Vec<String> {
+    return vec![
+        format!("-Xms{}", heap),
+        format!("-Xmx{}", heap),
+        "-XX:+UseZGC".to_string(),
+        "-XX:+DisableExplicitGC".to_string(),
+        "-XX:+AlwaysPreTouch".to_string(),
+        "-XX:+PerfDisableSharedMem".to_string(),
+    ];
+}
+
+/// Flags for running a server with Shenandoah, which like ZGC does most of its work concurrently to
+/// keep pauses short. Not every JDK build includes Shenandoah. `heap` is in the same format as for
+/// `aikars_flags`.
+fn shenandoah_flags(heap: &str) -> Vec<String> {
+    return vec![
+        format!("-Xms{}", heap),
+        format!("-Xmx{}", heap),
+        "-XX:+UseShenandoahGC".to_string(),
         "-XX:+ParallelRefProcEnabled".to_string(),
-        "-Dusing.aikars.flags=mcflags.emc.gs".to_string(),
-    ]);
+        "-XX:+DisableExplicitGC".to_string(),
+        "-XX:+AlwaysPreTouch".to_string(),
+        "-XX:+PerfDisableSharedMem".to_string(),
+    ];
 }
 
 #[derive(Deserialize)]
@@ -534,4 +1851,182 @@ struct RunnerConfig {
     jvm_args: Option<Vec<String>>,
     #[serde(rename = "serverArgs")]
     server_args: Option<Vec<String>>,
+    #[serde(rename = "minJava")]
+    min_java: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_memory_valid() {
+        assert_eq!(parse_memory("512m").ok(), Some("512m".to_string()));
+        assert_eq!(parse_memory("10G").ok(), Some("10g".to_string()));
+    }
+
+    #[test]
+    fn parse_memory_invalid() {
+        for value in &[
+            "",
+            "m",
+            "0m",
+            "10",
+            "10k",
+            "1.5g",
+            "-5m",
+            "99999999999999999999m",
+        ] {
+            assert!(parse_memory(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    fn has_flag(flags: &[String], flag: &str) -> bool {
+        return flags.iter().any(|f| f == flag);
+    }
+
+    #[test]
+    fn args_file() {
+        let file = env::temp_dir().join(format!("paperd-test-{}.args", std::process::id()));
+        fs::write(
+            &file,
+            "# Memory\n-Xms4G\n\n  -Xmx4G  \n\t# indented comment\n-Dmotd=Hello world\n",
+        )
+        .unwrap();
+
+        let args = read_args_file(&file).ok();
+        let _ = fs::remove_file(&file);
+        assert_eq!(
+            args,
+            Some(vec![
+                "-Xms4G".to_string(),
+                "-Xmx4G".to_string(),
+                "-Dmotd=Hello world".to_string(),
+            ])
+        );
+        assert!(read_args_file(&file).is_err());
+    }
+
+    #[test]
+    fn aikars_flags_small_heap() {
+        let flags = aikars_flags("10g");
+        assert!(has_flag(&flags, "-Xms10g"));
+        assert!(has_flag(&flags, "-Xmx10g"));
+        assert!(has_flag(&flags, "-XX:G1NewSizePercent=30"));
+        assert!(has_flag(&flags, "-XX:G1HeapRegionSize=8M"));
+    }
+
+    #[test]
+    fn aikars_flags_large_heap() {
+        let flags = aikars_flags("16g");
+        assert!(has_flag(&flags, "-XX:G1NewSizePercent=40"));
+        assert!(has_flag(&flags, "-XX:G1HeapRegionSize=16M"));
+    }
+
+    #[test]
+    fn aikars_flags_boundary() {
+        // Only heaps larger than 12G get the large heap flags
+        for heap in &["12g", "12288m"] {
+            let flags = aikars_flags(heap);
+            assert!(has_flag(&flags, "-XX:G1NewSizePercent=30"), "{}", heap);
+            assert!(has_flag(&flags, "-XX:G1HeapRegionSize=8M"), "{}", heap);
+        }
+
+        let flags = aikars_flags("12289m");
+        assert!(has_flag(&flags, "-XX:G1NewSizePercent=40"));
+        assert!(has_flag(&flags, "-XX:G1HeapRegionSize=16M"));
+    }
+
+    #[test]
+    fn low_pause_gc_presets() {
+        let presets: [(fn(&str) -> Vec<String>, &str); 2] = [
+            (zgc_flags, "-XX:+UseZGC"),
+            (shenandoah_flags, "-XX:+UseShenandoahGC"),
+        ];
+        for (preset, gc_flag) in &presets {
+            let flags = preset("6g");
+            assert!(has_flag(&flags, "-Xms6g"), "{}", gc_flag);
+            assert!(has_flag(&flags, "-Xmx6g"), "{}", gc_flag);
+            assert!(has_flag(&flags, gc_flag));
+            assert!(!has_flag(&flags, "-XX:+UseG1GC"), "{}", gc_flag);
+            assert!(
+                !flags.iter().any(|f| f.contains("G1")),
+                "{} sets G1 only flags",
+                gc_flag
+            );
+        }
+    }
+
+    /// Creates a directory holding empty jars with the given names, returning its path.
+    fn jar_dir(jars: &[&str]) -> PathBuf {
+        let dir = env::temp_dir().join(format!(
+            "paperd-test-{}-{}",
+            std::process::id(),
+            jars.join(",")
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        for jar in jars {
+            fs::write(dir.join(jar), "").unwrap();
+        }
+        return dir;
+    }
+
+    fn newest(jars: &[&str], name: Option<&str>) -> Option<String> {
+        let dir = jar_dir(jars);
+        let jar = newest_jar(&dir, name);
+        let _ = fs::remove_dir_all(&dir);
+        return jar
+            .ok()?
+            .map(|jar| jar.file_name().unwrap().to_string_lossy().to_string());
+    }
+
+    #[test]
+    fn jar_version_order() {
+        let version = |name: &str| jar_version(Path::new(name));
+        assert_eq!(version("paper-1.20.4-123.jar"), vec![1, 20, 4, 123]);
+        assert_eq!(version("paperclip.jar"), Vec::<u64>::new());
+        assert!(version("paper-1.20.4-123.jar") > version("paper-1.20.4-99.jar"));
+        assert!(version("paper-1.20.10-1.jar") > version("paper-1.20.9-500.jar"));
+        assert!(version("paper-1.21-1.jar") > version("paper-1.20.4-500.jar"));
+    }
+
+    #[test]
+    fn jar_names() {
+        assert_eq!(jar_name(Path::new("paper-1.20.4-123.jar")), "paper");
+        assert_eq!(jar_name(Path::new("velocity-3.3.0.jar")), "velocity");
+        assert_eq!(jar_name(Path::new("paperclip.jar")), "paperclip");
+        assert_eq!(jar_name(Path::new("1.20.4.jar")), "");
+    }
+
+    #[test]
+    fn newest_jar_by_version() {
+        assert_eq!(
+            newest(&["paper-1.20.4-99.jar", "paper-1.20.4-123.jar"], None),
+            Some("paper-1.20.4-123.jar".to_string())
+        );
+        assert_eq!(newest(&["notes.txt"], None), None);
+    }
+
+    #[test]
+    fn newest_jar_mixed_names() {
+        let jars = ["paper-1.20.4-123.jar", "velocity-3.3.0.jar"];
+        assert_eq!(
+            newest(&jars, None),
+            Some("paper-1.20.4-123.jar".to_string())
+        );
+        assert_eq!(
+            newest(&jars, Some("velocity")),
+            Some("velocity-3.3.0.jar".to_string())
+        );
+        assert_eq!(newest(&jars, Some("purpur")), None);
+        // Without Paper jars to prefer, it's up to the user which one to run
+        assert_eq!(
+            newest(&["purpur-1.20.4-2.jar", "velocity-3.3.0.jar"], None),
+            None
+        );
+        assert_eq!(
+            newest(&["purpur-1.20.4-2.jar", "purpur-1.20.4-10.jar"], None),
+            Some("purpur-1.20.4-10.jar".to_string())
+        );
+    }
 }
@@ -13,70 +13,150 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::log::{find_log_file, tail};
+use crate::client::Client;
+use crate::log::{find_log_file, tail_from, tail_lines};
 use crate::messaging::MessageSocket;
-use crate::protocol::check_protocol;
-use crate::util::{get_sock, ExitValue};
+use crate::util::{get_sock, ExitError, ExitValue};
 use clap::ArgMatches;
-use serde::Serialize;
-use std::sync::{Arc, Mutex};
-use std::thread::{sleep, spawn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::io::Read;
 use std::time::Duration;
 
+// Used when the server can't be reached at all, so scripts can tell that apart from a command which
+// failed to be delivered to a server we did connect to
+pub const UNREACHABLE_EXIT_CODE: i32 = 2;
+
+const DEFAULT_CAPTURE_TIMEOUT: u64 = 10;
+
 pub fn send(sub_m: &ArgMatches) -> Result<(), ExitValue> {
-    let (sock, sock_file) = get_sock(sub_m)?;
-    check_protocol(&sock)?;
-
-    let command: String = match sub_m.values_of("COMMAND") {
-        Some(s) => s.map(|a| a.to_string()).collect::<Vec<String>>().join(" "),
-        None => {
-            eprintln!("No command given.");
-            return Err(ExitValue::Code(1));
-        }
-    };
+    let commands = read_commands(sub_m)?;
+    if !sub_m.is_present("RAW") {
+        check_control_chars(&commands)?;
+    }
 
-    return if sub_m.is_present("TAIL") {
-        let lock: Arc<Mutex<Option<ExitValue>>> = Arc::new(Mutex::new(None));
-        let thread_lock = lock.clone();
-
-        // Start tailing in a separate thread so it won't be too late and miss the response
-        spawn(move || {
-            let mut exit_value = thread_lock.lock().unwrap();
-            let log_file = match find_log_file(&sock_file) {
-                Ok(f) => f,
-                Err(e) => {
-                    *exit_value = Some(e.clone());
-                    return;
-                }
-            };
-            if let Err(e) = tail(log_file, 0, true) {
-                *exit_value = Some(e.clone());
-            }
+    let (sock, sock_file) = get_sock(sub_m).map_err(|_| ExitValue::Code(UNREACHABLE_EXIT_CODE))?;
+    let client = Client::from_socket(sock, &sock_file)?;
+
+    if sub_m.is_present("CAPTURE") {
+        let timeout = Duration::from_secs(match sub_m.value_of("CAPTURE_TIMEOUT") {
+            Some(t) => t.parse::<u64>().conv("Failed to parse --capture-timeout")?,
+            None => DEFAULT_CAPTURE_TIMEOUT,
         });
+        return capture_commands(&client, &commands, timeout);
+    }
 
-        // Wait long enough to acquire the lock
-        sleep(Duration::from_millis(1));
+    if !sub_m.is_present("TAIL") {
+        return send_commands(&client, &commands);
+    }
+    let context = tail_lines(sub_m)?;
 
-        send_command(&sock, command.as_str())?;
+    // Remember where the log ends before the command is sent, so only the command's own output is
+    // shown. Anything the server happens to log between now and the command being sent is shown as
+    // well, but nothing the command logs can be missed
+    let log_file = find_log_file(sub_m, &sock_file)?;
+    let offset = fs::metadata(&log_file).map(|m| m.len()).unwrap_or(0);
 
-        // Wait for tail to complete before returning
-        let exit_value = lock.lock().unwrap();
-        if let Some(e) = &*exit_value {
-            Err(e.clone())
-        } else {
-            Ok(())
-        }
+    send_commands(&client, &commands)?;
+
+    return tail_from(log_file, offset, context);
+}
+
+/// Commands are read either from the command line, or one per line from stdin or a file. Each
+/// command is paired with the line it came from, for reporting errors.
+fn read_commands(sub_m: &ArgMatches) -> Result<Vec<(usize, String)>, ExitValue> {
+    if let Some(args) = sub_m.values_of("COMMAND") {
+        let command = args.collect::<Vec<&str>>().join(" ");
+        return Ok(vec![(1, command)]);
+    }
+
+    let text = if sub_m.is_present("STDIN") {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .conv("Failed to read commands from stdin")?;
+        text
+    } else if let Some(file) = sub_m.value_of("FILE") {
+        fs::read_to_string(file).conv(format!("Failed to read commands from {}", file))?
     } else {
-        Ok(())
+        eprintln!("No command given.");
+        return Err(ExitValue::Code(1));
     };
+
+    return Ok(text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim().to_string()))
+        .filter(|(_, line)| !line.is_empty())
+        .collect());
 }
 
-pub fn send_command(sock: &MessageSocket, cmd: &str) -> Result<(), ExitValue> {
-    let message = SendCommandMessage {
-        message: cmd.to_string(),
-    };
+/// The server's console reads commands one line at a time, so a newline or other control character
+/// in a command, from pasting it in for example, would confuse it. `--raw` sends them anyways.
+pub fn check_control_chars(commands: &[(usize, String)]) -> Result<(), ExitValue> {
+    for (line, command) in commands {
+        let c = match command.chars().find(|c| c.is_control()) {
+            Some(c) => c,
+            None => continue,
+        };
 
-    sock.send_message(&message)?;
+        let location = if commands.len() > 1 {
+            format!("The command on line {}", line)
+        } else {
+            "The command".to_string()
+        };
+        eprintln!(
+            "{} contains the control character '{}', which the server doesn't expect in a \
+             command. Use --raw to send it anyways.",
+            location,
+            c.escape_default()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    return Ok(());
+}
+
+fn send_commands(client: &Client, commands: &[(usize, String)]) -> Result<(), ExitValue> {
+    for (line, command) in commands {
+        if let Err(e) = client.send_command(command.as_str()) {
+            if commands.len() > 1 {
+                eprintln!("Failed to send command on line {}: {}", line, command);
+            }
+            return Err(e.into());
+        }
+    }
+
+    return Ok(());
+}
+
+/// Sends each command and prints the output the server captured for it, in order.
+fn capture_commands(
+    client: &Client,
+    commands: &[(usize, String)],
+    timeout: Duration,
+) -> Result<(), ExitValue> {
+    for (line, command) in commands {
+        let output = match client.capture_command(command.as_str(), timeout) {
+            Ok(output) => output,
+            Err(e) => {
+                if commands.len() > 1 {
+                    eprintln!("Failed to send command on line {}: {}", line, command);
+                }
+                return Err(e.into());
+            }
+        };
+        for output_line in output {
+            println!("{}", output_line);
+        }
+    }
+
+    return Ok(());
+}
+
+pub fn send_command(sock: &MessageSocket, cmd: &str) -> Result<(), ExitValue> {
+    sock.send_message(&SendCommandMessage::new(cmd))?;
 
     return Ok(());
 }
@@ -86,3 +166,25 @@ pub struct SendCommandMessage {
     #[serde(rename = "message")]
     message: String,
 }
+
+impl SendCommandMessage {
+    pub fn new(cmd: &str) -> Self {
+        return SendCommandMessage {
+            message: cmd.to_string(),
+        };
+    }
+}
+
+// Request
+#[derive(Serialize)]
+pub struct CaptureCommandMessage {
+    #[serde(rename = "message")]
+    pub message: String,
+}
+
+// Response
+#[derive(Serialize, Deserialize)]
+pub struct CaptureCommandMessageResponse {
+    #[serde(rename = "lines")]
+    pub lines: Vec<String>,
+}
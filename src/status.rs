@@ -13,27 +13,235 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::protocol::check_protocol;
+use crate::client::Client;
+use crate::output::{colored, Color};
+use crate::protocol::PROTOCOL_VERSION;
 use crate::util;
-use crate::util::{get_sock, ExitValue};
+use crate::util::{find_pid_file, get_sock, server_state, ExitError, ExitValue, ServerState};
 use clap::ArgMatches;
+use nix::libc::STDOUT_FILENO;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use nix::unistd::isatty;
+use paperd_lib::Socket;
 use serde::{Deserialize, Serialize};
+use signal_hook::iterator::Signals;
+use signal_hook::SIGINT;
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const DEFAULT_WATCH_INTERVAL: u64 = 2;
+
+// Exit codes for --check
+const CHECK_UNREACHABLE_EXIT_CODE: i32 = 1;
+const CHECK_DEGRADED_EXIT_CODE: i32 = 2;
 
 pub fn status(sub_m: &ArgMatches) -> Result<(), ExitValue> {
-    let (sock, _) = get_sock(sub_m)?;
-    check_protocol(&sock)?;
+    if sub_m.is_present("CHECK") {
+        return check(sub_m);
+    }
 
-    let message = StatusMessage {};
+    let (sock, sock_file) = get_sock(sub_m)?;
+    let client = Client::from_socket(sock, &sock_file)?;
 
-    sock.send_message(&message)?;
+    if sub_m.is_present("DEBUG") {
+        print_connection(&client, &sock_file);
+    }
 
-    let res = sock.receive_message::<StatusMessageResponse>()?;
+    if sub_m.is_present("WATCH") && isatty(STDOUT_FILENO).unwrap_or(false) {
+        let interval = match sub_m.value_of("WATCH") {
+            Some(i) => i.parse::<u64>().conv("Failed to parse --watch interval")?,
+            None => DEFAULT_WATCH_INTERVAL,
+        };
+        return watch(&client, Duration::from_secs(interval));
+    }
+
+    let res = client.status()?;
+
+    if sub_m.is_present("PLAYERS") {
+        return output_players(&res, sub_m.is_present("JSON"));
+    }
+
+    if sub_m.is_present("JSON") {
+        return output_json(&res);
+    }
 
     output_status(&res);
 
     return Ok(());
 }
 
+/// Prints which files paperd used to reach the server and who is on the other end of the socket,
+/// for `--debug`. This is what's needed to match paperd up with the output of tools like `ss -xp`
+/// or `lsof` when it can't talk to the server.
+fn print_connection(client: &Client, sock_file: &Path) {
+    let canonical = |path: &Path| {
+        return fs::canonicalize(path)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|e| format!("{} ({})", path.display(), e));
+    };
+
+    let fd = client.socket().raw_fd();
+    println!("Socket file:      {}", canonical(sock_file));
+    println!("Socket fd:        {}", fd);
+    print_peer(fd);
+
+    match find_pid_file(sock_file) {
+        Some(pid_file) => {
+            let state = match server_state(&pid_file) {
+                ServerState::Running { pid } => format!("PID {}, running", pid),
+                ServerState::StalePidFile { pid } => format!("PID {}, not running", pid),
+                ServerState::NotRunning => "not a valid PID".to_string(),
+                ServerState::NoPidFile => "missing".to_string(),
+            };
+            println!("PID file:         {} ({})", canonical(&pid_file), state);
+        }
+        None => println!("PID file:         none"),
+    }
+    println!("Protocol version: {}", PROTOCOL_VERSION);
+    println!();
+}
+
+/// The kernel knows which process accepted the connection, which may not be the one in the PID
+/// file if that's stale or was replaced.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn print_peer(fd: Socket) {
+    match getsockopt(fd, PeerCredentials) {
+        Ok(cred) => println!("Server process:   PID {}, UID {}", cred.pid(), cred.uid()),
+        Err(e) => println!("Server process:   unknown ({})", e),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn print_peer(_fd: Socket) {}
+
+/// Checks the health of the server for monitoring tools, which only look at the exit code. Nothing
+/// is printed to stdout, problems are described on stderr.
+fn check(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let min_tps = match sub_m.value_of("MIN_TPS").map(|v| v.parse::<f64>()) {
+        Some(Ok(tps)) => Some(tps),
+        Some(Err(e)) => {
+            eprintln!("Failed to parse --min-tps: {}", e);
+            return Err(ExitValue::Code(CHECK_UNREACHABLE_EXIT_CODE));
+        }
+        None => None,
+    };
+
+    let res = get_sock(sub_m).and_then(|(sock, sock_file)| {
+        let client = Client::from_socket(sock, &sock_file)?;
+        return Ok(client.status()?);
+    });
+    // Any failure to get the status means the server is unreachable, including the server telling
+    // us it's shutting down, which would otherwise exit successfully
+    let res = match res {
+        Ok(r) => r,
+        Err(_) => return Err(ExitValue::Code(CHECK_UNREACHABLE_EXIT_CODE)),
+    };
+
+    let problems = health_problems(&res, min_tps);
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    for problem in problems {
+        eprintln!("{}", problem);
+    }
+    return Err(ExitValue::Code(CHECK_DEGRADED_EXIT_CODE));
+}
+
+/// Compares the status against the given thresholds, returning a description of each one which
+/// isn't met.
+fn health_problems(status: &StatusMessageResponse, min_tps: Option<f64>) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    if let Some(min_tps) = min_tps {
+        let tps = util::tps_cap(status.tps.one_min);
+        if tps < min_tps {
+            problems.push(format!(
+                "TPS over the past minute is {:.2}, below the minimum of {:.2}",
+                tps, min_tps
+            ));
+        }
+    }
+
+    return problems;
+}
+
+/// Redraws the status every `interval` over the same connection until C-c is pressed.
+fn watch(client: &Client, interval: Duration) -> Result<(), ExitValue> {
+    let signals = Signals::new(&[SIGINT]).conv("Failed to register signal handlers")?;
+
+    let result = loop {
+        let res = match client.status() {
+            Ok(r) => r,
+            Err(e) => break Err(e.into()),
+        };
+
+        // Clear the screen and move the cursor back to the top
+        print!("\x1b[2J\x1b[H");
+        output_status(&res);
+        let _ = io::stdout().flush();
+
+        let start = Instant::now();
+        let mut interrupted = false;
+        while !interrupted && start.elapsed() < interval {
+            sleep(Duration::from_millis(100));
+            interrupted = signals.pending().next().is_some();
+        }
+        if interrupted {
+            break Ok(());
+        }
+    };
+
+    signals.close();
+    return result;
+}
+
+fn output_players(status: &StatusMessageResponse, json: bool) -> Result<(), ExitValue> {
+    if json {
+        return match serde_json::to_string(&status.players) {
+            Ok(json) => {
+                println!("{}", json);
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Failed to serialize JSON: {}", e);
+                Err(ExitValue::Code(1))
+            }
+        };
+    }
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for player in &status.players {
+        // stdout being closed early (piping into head, for example) isn't an error
+        if writeln!(out, "{}", player).is_err() {
+            break;
+        }
+    }
+
+    return Ok(());
+}
+
+fn output_json(status: &StatusMessageResponse) -> Result<(), ExitValue> {
+    return match serde_json::to_string_pretty(status) {
+        Ok(json) => {
+            println!("{}", json);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Failed to serialize JSON: {}", e);
+            Err(ExitValue::Code(1))
+        }
+    };
+}
+
 #[cfg_attr(rustfmt, rustfmt_skip)]
 fn output_status(status: &StatusMessageResponse) {
     let line_length = 60;
@@ -41,7 +249,15 @@ fn output_status(status: &StatusMessageResponse) {
     println!("======================= Server Info =======================");
     println!("   Server | {}", status.server_name);
     println!("     MOTD | {}", status.motd);
-    print_players(&status.players, "  Players", line_length);
+    match status.phase {
+        Some(ServerPhase::Starting) => println!("    Phase | Starting (TPS isn't meaningful yet)"),
+        Some(phase) => println!("    Phase | {}", phase),
+        None => {}
+    }
+    if let Some(uptime) = status.uptime {
+        println!("   Uptime | {}", format_uptime(uptime));
+    }
+    print_players(&status.players, status.max_players, "  Players", line_length);
     println!();
     println!("------------------------- Version --------------------------");
     println!("  PaperMC Server Version | {}", status.server_version);
@@ -55,7 +271,7 @@ fn output_status(status: &StatusMessageResponse) {
         println!("  Dimension | {}", world.dimension);
         println!("       Seed | {}", world.seed);
         println!(" Difficulty | {}", world.difficulty);
-        print_players(&world.players, "    Players", line_length);
+        print_players(&world.players, None, "    Players", line_length);
         println!("       Time | {}", format_time(world.time.as_str()));
         println!("************************************************************");
     }
@@ -63,22 +279,50 @@ fn output_status(status: &StatusMessageResponse) {
     println!();
     println!("-------------------- Server Performance --------------------");
     println!("  TPS");
-    println!("    Past 1 Minute   | {:.2}", util::tps_cap(status.tps.one_min));
-    println!("    Past 5 Minutes  | {:.2}", util::tps_cap(status.tps.five_min));
-    println!("    Past 15 Minutes | {:.2}", util::tps_cap(status.tps.fifteen_min));
+    println!("    Past 1 Minute   | {}", format_tps(status.tps.one_min));
+    println!("    Past 5 Minutes  | {}", format_tps(status.tps.five_min));
+    println!("    Past 15 Minutes | {}", format_tps(status.tps.fifteen_min));
+    if !status.tps.history.is_empty() {
+        println!("    Recent          | {}", util::tps_sparkline(&status.tps.history));
+    }
     println!();
     println!("  Memory Usage");
     println!("    Memory Currently Used   | {}", status.memory_usage.used_memory);
     println!("    Total Memory Allocated  | {}", status.memory_usage.total_memory);
     println!("    Maximum Possible Memory | {}", status.memory_usage.max_memory);
+
+    if let Some(heap) = &status.heap {
+        println!();
+        println!("  Heap");
+        println!("    Used      | {}", util::format_bytes(heap.used));
+        println!("    Committed | {}", util::format_bytes(heap.committed));
+        println!("    Maximum   | {}", util::format_bytes(heap.max));
+    }
+
+    if !status.gc.is_empty() {
+        println!();
+        println!("  Garbage Collection");
+        for gc in &status.gc {
+            println!("    {}", gc.name);
+            println!("      Collections | {}", gc.collections);
+            println!("      Total Time  | {} ms", gc.total_time);
+            if let Some(last_pause) = gc.last_pause {
+                println!("      Last Pause  | {} ms", last_pause);
+            }
+        }
+    }
     println!();
 }
 
-fn print_players(players: &Vec<String>, prefix: &str, length: usize) {
+fn print_players(players: &Vec<String>, max: Option<i32>, prefix: &str, length: usize) {
     let mut current_line = String::with_capacity(length);
     current_line.push_str(prefix);
     current_line.push_str(" | (");
     current_line.push_str(players.len().to_string().as_str());
+    if let Some(max) = max {
+        current_line.push('/');
+        current_line.push_str(max.to_string().as_str());
+    }
     current_line.push_str(") ");
 
     for (i, player) in players.iter().enumerate() {
@@ -101,6 +345,37 @@ fn print_players(players: &Vec<String>, prefix: &str, length: usize) {
     }
 }
 
+/// Colors the TPS by how far behind the server is: green is close to 20, yellow is noticeably lagging,
+/// and red is badly lagging.
+fn format_tps(tps: f64) -> String {
+    let tps = util::tps_cap(tps);
+    let color = if tps >= 18.0 {
+        Color::Green
+    } else if tps >= 15.0 {
+        Color::Yellow
+    } else {
+        Color::Red
+    };
+    return colored(format!("{:.2}", tps), color);
+}
+
+fn format_uptime(uptime: u64) -> String {
+    let days = uptime / 86400;
+    let hours = (uptime % 86400) / 3600;
+    let minutes = (uptime % 3600) / 60;
+    let seconds = uptime % 60;
+
+    return if days > 0 {
+        format!("{}d {}h {}m {}s", days, hours, minutes, seconds)
+    } else if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    };
+}
+
 fn format_time(time: &str) -> String {
     let mut res = String::with_capacity(5);
     res.push_str(&time[..time.len() / 2]);
@@ -114,58 +389,175 @@ fn format_time(time: &str) -> String {
 pub struct StatusMessage {}
 
 // Response
-#[derive(Deserialize)]
-pub(crate) struct StatusMessageResponse {
+#[derive(Serialize, Deserialize)]
+pub struct StatusMessageResponse {
     #[serde(rename = "motd")]
-    motd: String,
+    pub motd: String,
     #[serde(rename = "serverName")]
-    server_name: String,
+    pub server_name: String,
     #[serde(rename = "serverVersion")]
-    server_version: String,
+    pub server_version: String,
     #[serde(rename = "apiVersion")]
-    api_version: String,
+    pub api_version: String,
+    #[serde(rename = "phase", default)]
+    pub phase: Option<ServerPhase>,
+    /// Seconds since the server started
+    #[serde(rename = "uptime", default)]
+    pub uptime: Option<u64>,
     #[serde(rename = "players")]
-    players: Vec<String>,
+    pub players: Vec<String>,
+    #[serde(rename = "maxPlayers", default)]
+    pub max_players: Option<i32>,
     #[serde(rename = "worlds")]
-    worlds: Vec<WorldStatus>,
+    pub worlds: Vec<WorldStatus>,
     #[serde(rename = "tps")]
-    tps: TpsStatus,
+    pub tps: TpsStatus,
     #[serde(rename = "memoryUsage")]
-    memory_usage: MemoryStatus,
+    pub memory_usage: MemoryStatus,
+    #[serde(rename = "heap", default)]
+    pub heap: Option<HeapStatus>,
+    #[serde(rename = "gc", default)]
+    pub gc: Vec<GcStatus>,
+}
+
+impl StatusMessageResponse {
+    /// Where the server is in its lifecycle, if the server reports it.
+    pub fn phase(&self) -> Option<ServerPhase> {
+        return self.phase;
+    }
+
+    /// How many players are online.
+    pub fn player_count(&self) -> usize {
+        return self.players.len();
+    }
+
+    /// The names of the worlds the server has loaded, which are also the names of their
+    /// directories.
+    pub fn world_names(&self) -> Vec<&str> {
+        return self.worlds.iter().map(|w| w.name.as_str()).collect();
+    }
+}
+
+/// Where the server is in its lifecycle.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ServerPhase {
+    Starting,
+    Running,
+    Stopping,
+}
+
+impl Display for ServerPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ServerPhase::Starting => write!(f, "Starting"),
+            ServerPhase::Running => write!(f, "Running"),
+            ServerPhase::Stopping => write!(f, "Stopping"),
+        };
+    }
 }
 
-#[derive(Deserialize)]
-struct WorldStatus {
+#[derive(Serialize, Deserialize)]
+pub struct WorldStatus {
     #[serde(rename = "name")]
-    name: String,
+    pub name: String,
     #[serde(rename = "dimension")]
-    dimension: String,
+    pub dimension: String,
     #[serde(rename = "seed")]
-    seed: i64,
+    pub seed: i64,
     #[serde(rename = "difficulty")]
-    difficulty: String,
+    pub difficulty: String,
     #[serde(rename = "players")]
-    players: Vec<String>,
+    pub players: Vec<String>,
     #[serde(rename = "time")]
-    time: String,
+    pub time: String,
 }
 
-#[derive(Deserialize)]
-struct TpsStatus {
+/// Average ticks per second over the last 1, 5 and 15 minutes.
+#[derive(Serialize, Deserialize)]
+pub struct TpsStatus {
     #[serde(rename = "oneMin")]
-    one_min: f64,
+    pub one_min: f64,
     #[serde(rename = "fiveMin")]
-    five_min: f64,
+    pub five_min: f64,
     #[serde(rename = "fifteenMin")]
-    fifteen_min: f64,
+    pub fifteen_min: f64,
+    #[serde(rename = "history", default)]
+    pub history: Vec<f64>,
 }
 
-#[derive(Deserialize)]
-struct MemoryStatus {
+/// Memory usage, already formatted for display by the server.
+#[derive(Serialize, Deserialize)]
+pub struct MemoryStatus {
     #[serde(rename = "usedMemory")]
-    used_memory: String,
+    pub used_memory: String,
     #[serde(rename = "totalMemory")]
-    total_memory: String,
+    pub total_memory: String,
     #[serde(rename = "maxMemory")]
-    max_memory: String,
+    pub max_memory: String,
+}
+
+/// JVM heap usage in bytes.
+#[derive(Serialize, Deserialize)]
+pub struct HeapStatus {
+    #[serde(rename = "used")]
+    pub used: u64,
+    #[serde(rename = "committed")]
+    pub committed: u64,
+    #[serde(rename = "max")]
+    pub max: u64,
+}
+
+/// A garbage collector's totals, times are in milliseconds.
+#[derive(Serialize, Deserialize)]
+pub struct GcStatus {
+    #[serde(rename = "name")]
+    pub name: String,
+    #[serde(rename = "collections")]
+    pub collections: u64,
+    #[serde(rename = "totalTime")]
+    pub total_time: u64,
+    #[serde(rename = "lastPause", default)]
+    pub last_pause: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_with_tps(tps: f64) -> StatusMessageResponse {
+        let text = format!(
+            r#"{{
+                "motd": "", "serverName": "Paper", "serverVersion": "", "apiVersion": "",
+                "players": [], "worlds": [],
+                "tps": {{"oneMin": {}, "fiveMin": 20.0, "fifteenMin": 20.0}},
+                "memoryUsage": {{"usedMemory": "", "totalMemory": "", "maxMemory": ""}}
+            }}"#,
+            tps
+        );
+        return serde_json::from_str(&text).unwrap();
+    }
+
+    #[test]
+    fn health_without_thresholds() {
+        assert!(health_problems(&status_with_tps(1.0), None).is_empty());
+    }
+
+    #[test]
+    fn health_min_tps() {
+        assert!(health_problems(&status_with_tps(19.5), Some(18.0)).is_empty());
+        assert!(health_problems(&status_with_tps(18.0), Some(18.0)).is_empty());
+
+        let problems = health_problems(&status_with_tps(15.0), Some(18.0));
+        assert_eq!(
+            problems,
+            vec!["TPS over the past minute is 15.00, below the minimum of 18.00"]
+        );
+    }
+
+    #[test]
+    fn health_tps_capped() {
+        // TPS above 20 counts as 20, the most the server runs at
+        assert!(health_problems(&status_with_tps(25.0), Some(20.0)).is_empty());
+        assert_eq!(health_problems(&status_with_tps(25.0), Some(20.5)).len(), 1);
+    }
 }
@@ -13,54 +13,98 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use crate::protocol::check_protocol;
-use crate::util::{find_sock_file, get_pid, get_sock_from_file, ExitValue};
+use crate::client::Client;
+use crate::info;
+use crate::output::progress;
+use crate::util::{find_sock_file, get_pid, get_sock_from_file, ExitError, ExitValue};
 use clap::ArgMatches;
 use nix::errno::Errno::ESRCH;
+use nix::libc::STDIN_FILENO;
 use nix::sys::signal::{kill, SIGKILL};
-use nix::unistd::Pid;
+use nix::unistd::{isatty, Pid};
 use nix::Error;
 use serde::Serialize;
-use std::io::Write;
+use std::fs;
+use std::io::{stdin, stdout, Write};
 use std::path::Path;
 use std::thread::sleep;
-use std::time::Duration;
-use std::{fs, io};
+use std::time::{Duration, Instant};
+
+const DEFAULT_STOP_TIMEOUT: u64 = 30;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
 
 pub fn stop(sub_m: &ArgMatches) -> Result<(), ExitValue> {
     let sock_file = find_sock_file(sub_m)?;
     let (pid_file, pid) = get_pid(&sock_file)?;
 
+    let timeout = Duration::from_secs(match sub_m.value_of("TIMEOUT") {
+        Some(t) => t.parse::<u64>().conv("Failed to parse --timeout")?,
+        None => DEFAULT_STOP_TIMEOUT,
+    });
+
+    let message = sub_m.value_of("MESSAGE");
+    if let Some(c) = message.and_then(|m| m.chars().find(|c| c.is_control())) {
+        eprintln!(
+            "The message contains the control character '{}', which can't be broadcast",
+            c.escape_default()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    if sub_m.is_present("DRY_RUN") {
+        print_plan(sub_m, &sock_file, &pid_file, pid, timeout);
+        return Ok(());
+    }
+
+    let destructive = sub_m.is_present("KILL") || sub_m.is_present("FORCE");
+    if destructive && !sub_m.is_present("YES") && !confirm_kill() {
+        eprintln!("Not stopping the server");
+        return Err(ExitValue::Code(1));
+    }
+
     if sub_m.is_present("KILL") {
         force_kill(&sock_file, &pid_file, pid);
-        println!("Server killed");
+        info!("Server killed");
         return Ok(());
     }
 
     let sock = get_sock_from_file(&sock_file)?;
-    check_protocol(&sock)?;
+    let client = Client::from_socket(sock, &sock_file)?;
 
-    let message = StopMessage {};
+    // Players are told why they're being dropped right before the server stops
+    if let Some(message) = message {
+        info!("Broadcasting shutdown message to the players..");
+        client.send_command(&format!("say {}", message))?;
+    }
 
-    println!("Sending stop command to the server..");
-    sock.send_message(&message)?;
+    info!("Sending stop command to the server..");
+    client.stop(true)?;
 
-    print!("Waiting for server to exit.");
-    let _ = io::stdout().flush();
+    let start = Instant::now();
+    progress("Waiting for server to exit.");
     // If -f is set then we need to wait to see if it fails
-    for _ in 0..30 {
+    let mut last_progress = start;
+    while start.elapsed() < timeout {
         if let Err(_) = kill(pid, None) {
             break;
         }
-        sleep(Duration::from_millis(500));
-        print!(".");
-        let _ = io::stdout().flush();
+        sleep(POLL_INTERVAL);
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            progress(".");
+            last_progress = Instant::now();
+        }
     }
-    println!();
+    info!();
 
     if let Err(Error::Sys(e)) = kill(pid, None) {
         return if e == ESRCH {
-            println!("Server exited successfully");
+            info!(
+                "Server exited successfully after {:.1} seconds",
+                start.elapsed().as_secs_f64()
+            );
+            // These are normally cleaned up on exit, but make sure they don't stick around if not
+            remove_files(&sock_file, &pid_file);
             Ok(())
         } else {
             println!("Unknown error occurred (stop): {}", e);
@@ -69,19 +113,81 @@ pub fn stop(sub_m: &ArgMatches) -> Result<(), ExitValue> {
     }
 
     if !sub_m.is_present("FORCE") {
-        println!("Server failed to exit cleanly");
+        println!(
+            "Server failed to exit cleanly within {} seconds",
+            timeout.as_secs()
+        );
         return Err(ExitValue::Code(1));
     }
 
-    println!("Server failed to exit cleanly, killing now");
+    info!(
+        "Server failed to exit cleanly within {} seconds, killing now",
+        timeout.as_secs()
+    );
     force_kill(&sock_file, &pid_file, pid);
-    println!("Server killed");
+    info!(
+        "Server killed after {:.1} seconds",
+        start.elapsed().as_secs_f64()
+    );
 
     return Ok(());
 }
 
+/// Prints what would be done for `--dry-run`, without doing any of it.
+fn print_plan(sub_m: &ArgMatches, sock_file: &Path, pid_file: &Path, pid: Pid, timeout: Duration) {
+    println!("Socket file: {}", sock_file.display());
+    println!("PID file:    {}", pid_file.display());
+    println!("PID:         {}", pid);
+    println!("Actions:");
+
+    if sub_m.is_present("KILL") {
+        println!("    Kill PID {} immediately with SIGKILL", pid);
+    } else {
+        if let Some(message) = sub_m.value_of("MESSAGE") {
+            println!("    Broadcast \"{}\" to the players", message);
+        }
+        println!("    Send a stop request to the server through the socket file");
+        println!(
+            "    Wait up to {} seconds for PID {} to exit",
+            timeout.as_secs(),
+            pid
+        );
+        if sub_m.is_present("FORCE") {
+            println!(
+                "    Kill PID {} with SIGKILL if it hasn't exited by then",
+                pid
+            );
+        } else {
+            println!("    Fail if it hasn't exited by then");
+        }
+    }
+    println!("    Remove the socket file and PID file if they're left behind");
+}
+
+/// Asks whether to go ahead with killing the server. Scripts aren't blocked waiting for an answer,
+/// if stdin isn't a terminal this always continues.
+fn confirm_kill() -> bool {
+    if !isatty(STDIN_FILENO).unwrap_or(false) {
+        return true;
+    }
+
+    print!("This may cause data loss. Continue? [y/N] ");
+    let _ = stdout().flush();
+
+    let mut answer = String::new();
+    if stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    let answer = answer.trim();
+    return answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes");
+}
+
 fn force_kill<P: AsRef<Path>>(sock_file: P, pid_file: P, pid: Pid) {
     let _ = kill(pid, SIGKILL);
+    remove_files(sock_file, pid_file);
+}
+
+fn remove_files<P: AsRef<Path>>(sock_file: P, pid_file: P) {
     let _ = fs::remove_file(&sock_file);
     let _ = fs::remove_file(&pid_file);
 }
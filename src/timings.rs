@@ -15,21 +15,44 @@
 
 #[cfg(feature = "console")]
 use crate::console::ansi;
+use crate::info;
 use crate::protocol::check_protocol;
-use crate::util::{get_sock, ExitValue};
+use crate::util::{get_sock, ExitError, ExitValue};
 use clap::ArgMatches;
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
 
+// Reports are far larger than any other message, so they get a larger limit of their own
+const MAX_REPORT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// With `--out` the report data is saved as the server returns it. paperd never downloads the report
+/// from the timings URL, so this works without internet access, but only with servers which can
+/// return the report data.
 pub fn timings(sub_m: &ArgMatches) -> Result<(), ExitValue> {
-    let (sock, _) = get_sock(sub_m)?;
+    let (mut sock, _) = get_sock(sub_m)?;
     check_protocol(&sock)?;
 
-    let message = TimingsMessage {};
+    let out_file = sub_m.value_of("OUT").map(Path::new);
+    if out_file.is_some() {
+        sock.set_max_message_length(MAX_REPORT_LENGTH);
+    }
+
+    let message = TimingsMessage {
+        include_report: out_file.is_some(),
+    };
 
     sock.send_message(&message)?;
 
+    // Generating the report can take a while, let the user know we're not stuck
+    info!("Generating timings report...");
+
+    let mut report = None;
     loop {
         let res = sock.receive_message::<TimingsMessageResponse>()?;
+        if res.report.is_some() {
+            report = res.report;
+        }
         if res.done {
             break;
         }
@@ -45,6 +68,39 @@ pub fn timings(sub_m: &ArgMatches) -> Result<(), ExitValue> {
         }
     }
 
+    if let Some(out_file) = out_file {
+        return save_report(out_file, report);
+    }
+
+    return Ok(());
+}
+
+fn save_report(out_file: &Path, report: Option<serde_json::Value>) -> Result<(), ExitValue> {
+    let report = match report {
+        Some(report) => report,
+        None => {
+            eprintln!(
+                "The server didn't return the report data, so nothing was saved to {}. This \
+                 server only supports sharing the report through the URL above.",
+                out_file.display()
+            );
+            return Err(ExitValue::Code(1));
+        }
+    };
+
+    let json = match serde_json::to_string_pretty(&report) {
+        Ok(json) => json,
+        Err(e) => {
+            eprintln!("Failed to serialize JSON: {}", e);
+            return Err(ExitValue::Code(1));
+        }
+    };
+    fs::write(out_file, json).conv(format!(
+        "Failed to write timings report to {}",
+        out_file.display()
+    ))?;
+
+    info!("Saved timings report to {}", out_file.display());
     return Ok(());
 }
 
@@ -68,7 +124,10 @@ fn mc_colors(s: &str) -> String {
 
 // Request
 #[derive(Serialize)]
-pub struct TimingsMessage {}
+pub struct TimingsMessage {
+    #[serde(rename = "includeReport")]
+    include_report: bool,
+}
 
 // Response
 #[derive(Serialize, Deserialize)]
@@ -77,4 +136,6 @@ struct TimingsMessageResponse {
     message: Option<String>,
     #[serde(rename = "done")]
     done: bool,
+    #[serde(rename = "report", default)]
+    report: Option<serde_json::Value>,
 }
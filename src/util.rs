@@ -13,14 +13,23 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
+use crate::instance::find_instance;
 use crate::messaging::MessageSocket;
 use crate::runner;
 use crate::runner::PID_FILE_NAME;
+use crate::{debug, verbose};
 use clap::ArgMatches;
+use nix::errno::Errno::{EACCES, ECONNREFUSED, ENOENT, ESRCH};
+use nix::libc;
+use nix::sys::signal::kill;
 use nix::unistd::Pid;
-use paperd_lib::{connect_socket, Error};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use paperd_lib::connect_abstract_socket;
+use paperd_lib::{close_socket, connect_socket, Error, Socket};
 use std::num::ParseIntError;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, fs, io};
 
 pub fn get_sock(sub_m: &ArgMatches) -> Result<(MessageSocket, PathBuf), ExitValue> {
@@ -31,32 +40,213 @@ pub fn get_sock(sub_m: &ArgMatches) -> Result<(MessageSocket, PathBuf), ExitValu
 }
 
 pub fn find_sock_file(sub_m: &ArgMatches) -> Result<PathBuf, ExitValue> {
-    let sock_file = sub_m
-        .value_of("SOCK")
-        .map(PathBuf::from)
-        .or_else(|| env::var_os("PAPERD_SOCK").map(PathBuf::from))
-        .unwrap_or_else(|| PathBuf::from(runner::SOCK_FILE_NAME));
-
-    if !sock_file.exists() {
-        eprintln!("No socket file found to send commands to");
-        return Err(ExitValue::Code(1));
+    let sock_file = sock_file_path(sub_m)?;
+
+    if let Err(e) = fs::metadata(&sock_file) {
+        // Servers listening on an abstract socket don't need the file
+        match connect_abstract(&sock_file) {
+            Some(sock) => {
+                let _ = close_socket(sock);
+            }
+            None => {
+                report_sock_file_error(&sock_file, e.kind());
+                return Err(ExitValue::Code(1));
+            }
+        }
     }
 
+    verbose!("Using socket file {}", sock_file.to_string_lossy());
+    return Ok(sock_file);
+}
+
+/// The socket file the arguments refer to, whether or not it exists.
+pub fn sock_file_path(sub_m: &ArgMatches) -> Result<PathBuf, ExitValue> {
+    let (sock_file, source) = match (sub_m.value_of("SOCK"), sub_m.value_of("INSTANCE")) {
+        (Some(sock), _) => (PathBuf::from(sock), "--sock"),
+        (None, Some(name)) => (
+            find_instance(name)?.join(runner::SOCK_FILE_NAME),
+            "--instance",
+        ),
+        (None, None) => match env::var_os("PAPERD_SOCK") {
+            Some(sock) => (PathBuf::from(sock), "PAPERD_SOCK"),
+            None => match find_server_dir() {
+                Some(dir) => (dir.join(runner::SOCK_FILE_NAME), "parent directory"),
+                None => (PathBuf::from(runner::SOCK_FILE_NAME), "current directory"),
+            },
+        },
+    };
+
+    debug!(
+        "sock_file.resolve",
+        path = sock_file.display(),
+        source = source
+    );
     return Ok(sock_file);
 }
 
+/// The working directory of the server listening on `sock_file`, read from the launch state next to
+/// the socket file. Servers not started by paperd have no launch state, for those it's assumed to be
+/// the directory the socket file is in.
+pub fn server_working_dir(sock_file: &Path) -> PathBuf {
+    let sock_dir = sock_file.parent().unwrap_or_else(|| Path::new("."));
+    return runner::read_launch_state(&sock_dir.join(runner::LAUNCH_FILE_NAME))
+        .map(|env| env.working_dir().to_path_buf())
+        .unwrap_or_else(|_| sock_dir.to_path_buf());
+}
+
+/// When run from somewhere inside a server's directory, finds the server's directory by walking up
+/// from the current directory to the closest one with a socket or PID file in it, like git does
+/// for `.git`. The walk stops at the user's home directory or the filesystem root. Returns `None`
+/// if the current directory is a server's directory itself, so the socket file is used as usual.
+fn find_server_dir() -> Option<PathBuf> {
+    let is_server_dir =
+        |dir: &Path| dir.join(runner::SOCK_FILE_NAME).exists() || dir.join(PID_FILE_NAME).exists();
+
+    let current_dir = env::current_dir().ok()?;
+    if is_server_dir(&current_dir) {
+        return None;
+    }
+
+    let home = env::var_os("HOME").map(PathBuf::from);
+    for dir in current_dir.ancestors().skip(1) {
+        if is_server_dir(dir) {
+            verbose!("Found server directory {}", dir.display());
+            return Some(dir.to_path_buf());
+        }
+        if home.as_ref().map_or(false, |home| home == dir) {
+            break;
+        }
+    }
+
+    return None;
+}
+
 pub fn get_sock_from_file_direct<P: AsRef<Path>>(sock_file: P) -> Result<MessageSocket, Error> {
-    let sock = connect_socket(sock_file.as_ref())?;
+    let sock_file = sock_file.as_ref();
+    let sock = match connect_socket(sock_file) {
+        Ok(sock) => sock,
+        Err(e) => {
+            debug!("socket.connect", path = sock_file.display(), error = e);
+            return match connect_abstract(sock_file) {
+                Some(sock) => Ok(MessageSocket::new(sock)),
+                None => Err(e),
+            };
+        }
+    };
+    debug!("socket.connect", path = sock_file.display(), fd = sock);
 
     return Ok(MessageSocket::new(sock));
 }
 
+/// Servers started with `--abstract-socket` listen on an abstract socket named after the path of
+/// their socket file instead of on the file itself, so they can still be reached if the socket file
+/// is missing, or if something else has taken its place.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn connect_abstract(sock_file: &Path) -> Option<Socket> {
+    let name = abstract_socket_name(sock_file);
+    return match connect_abstract_socket(&name) {
+        Ok(sock) => {
+            debug!("socket.connect", name = name, fd = sock);
+            Some(sock)
+        }
+        Err(e) => {
+            debug!("socket.connect", name = name, error = e);
+            None
+        }
+    };
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn connect_abstract(_sock_file: &Path) -> Option<Socket> {
+    return None;
+}
+
+/// The name of the abstract socket a server started with `--abstract-socket` listens on. The socket
+/// file may not exist, so the path is canonicalized the same way it's displayed.
+pub fn abstract_socket_name(sock_file: &Path) -> String {
+    let path = display_sock_path(sock_file);
+    return paperd_lib::abstract_socket_name(path.as_os_str().as_bytes());
+}
+
 pub fn get_sock_from_file<P: AsRef<Path>>(sock_file: P) -> Result<MessageSocket, ExitValue> {
-    let msg = format!(
-        "Failed to connect to socket {}",
-        sock_file.as_ref().display()
-    );
-    return get_sock_from_file_direct(sock_file.as_ref()).conv(msg);
+    let sock_file = sock_file.as_ref();
+    let err = match get_sock_from_file_direct(sock_file) {
+        Ok(sock) => return Ok(sock),
+        Err(e) => e,
+    };
+
+    // Servers which don't shut down cleanly leave their socket file behind, so figure out whether
+    // the server is actually still there
+    if let Some(pid_file) = find_pid_file(sock_file) {
+        match server_state(&pid_file) {
+            ServerState::StalePidFile { .. } => {
+                remove_stale_files(&pid_file, sock_file);
+                return Err(ExitValue::Code(1));
+            }
+            ServerState::Running { pid } => {
+                let msg = format!(
+                    "The server is running with PID {}, but is not accepting connections on \
+                     socket {}",
+                    pid,
+                    sock_file.display()
+                );
+                return Err(err).conv(msg);
+            }
+            ServerState::NotRunning | ServerState::NoPidFile => {}
+        }
+    }
+
+    if let Error::Nix(nix::Error::Sys(ECONNREFUSED), _) = err {
+        eprintln!(
+            "No server is running on socket {}. The socket file may have been left behind by a \
+             server which didn't shut down cleanly.",
+            sock_file.display()
+        );
+        return Err(ExitValue::Code(1));
+    }
+
+    match err {
+        Error::Nix(nix::Error::Sys(ENOENT), _) => {
+            report_sock_file_error(sock_file, io::ErrorKind::NotFound);
+            return Err(ExitValue::Code(1));
+        }
+        Error::Nix(nix::Error::Sys(EACCES), _) => {
+            report_sock_file_error(sock_file, io::ErrorKind::PermissionDenied);
+            return Err(ExitValue::Code(1));
+        }
+        _ => {}
+    }
+
+    let msg = format!("Failed to connect to socket {}", sock_file.display());
+    return Err(err).conv(msg);
+}
+
+/// Explains why the socket file can't be used. A missing socket file most likely means the server
+/// isn't running, while a permission error means it's most likely running as another user.
+fn report_sock_file_error(sock_file: &Path, kind: io::ErrorKind) {
+    let path = display_sock_path(sock_file);
+    if kind == io::ErrorKind::PermissionDenied {
+        eprintln!(
+            "Permission denied while accessing socket file {}. The server may be running as \
+             another user, run paperd as the same user as the server.",
+            path.display()
+        );
+    } else {
+        eprintln!("No server found at {} (is it running?)", path.display());
+    }
+}
+
+/// The full path of the socket file, to show where paperd looked. The socket file may not exist,
+/// so only its directory is canonicalized, and the path is shown as given if that fails too.
+fn display_sock_path(sock_file: &Path) -> PathBuf {
+    let dir = match sock_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    return match (fs::canonicalize(dir), sock_file.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => sock_file.to_path_buf(),
+    };
 }
 
 pub fn find_program(searches: &[(&str, &str)]) -> Option<PathBuf> {
@@ -79,8 +269,14 @@ pub fn find_program(searches: &[(&str, &str)]) -> Option<PathBuf> {
         .next();
 }
 
+/// The PID file of a server lives next to its socket file.
+pub fn find_pid_file<P: AsRef<Path>>(sock_file: P) -> Option<PathBuf> {
+    return sock_file.as_ref().parent().map(|p| p.join(PID_FILE_NAME));
+}
+
 pub fn get_pid<P: AsRef<Path>>(sock_file: P) -> Result<(PathBuf, Pid), ExitValue> {
-    let pid_file = match sock_file.as_ref().parent().map(|p| p.join(PID_FILE_NAME)) {
+    let sock_file = sock_file.as_ref();
+    let pid_file = match find_pid_file(sock_file) {
         Some(path) => path,
         None => {
             eprintln!("Failed to find PID file {}", PID_FILE_NAME);
@@ -88,24 +284,161 @@ pub fn get_pid<P: AsRef<Path>>(sock_file: P) -> Result<(PathBuf, Pid), ExitValue
         }
     };
 
-    let pid_text = fs::read_to_string(&pid_file).conv("Failed to read PID file")?;
-    let pid = Pid::from_raw(match pid_text.parse::<i32>() {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Failed to parse PID file: {}", e);
+    return match server_state(&pid_file) {
+        ServerState::Running { pid } => {
+            verbose!("Using PID file {}", pid_file.to_string_lossy());
+            Ok((pid_file, pid))
+        }
+        ServerState::StalePidFile { .. } => {
+            remove_stale_files(&pid_file, sock_file);
+            Err(ExitValue::Code(1))
+        }
+        ServerState::NotRunning => {
+            eprintln!("Failed to parse PID file {}", pid_file.display());
             fs::remove_file(&pid_file).conv("Failed to delete PID file")?;
             eprintln!("No server found to send commands to");
-            return Err(ExitValue::Code(1));
+            Err(ExitValue::Code(1))
+        }
+        ServerState::NoPidFile => {
+            eprintln!("Failed to find PID file {}", pid_file.display());
+            Err(ExitValue::Code(1))
         }
-    });
+    };
+}
 
-    return Ok((pid_file, pid));
+/// Whether the server a PID file belongs to is running, as returned by `server_state`.
+#[derive(Debug, PartialEq)]
+pub enum ServerState {
+    /// The process in the PID file is running
+    Running { pid: Pid },
+    /// The process in the PID file isn't running anymore, the server most likely crashed or was
+    /// killed before it could remove the PID file
+    StalePidFile { pid: Pid },
+    /// The PID file couldn't be read or doesn't contain a valid PID, so it can't belong to a running
+    /// server
+    NotRunning,
+    /// There is no PID file, either the server isn't running or it wasn't started by paperd
+    NoPidFile,
+}
+
+/// Reads the PID file and checks whether its process is still running.
+pub fn server_state<P: AsRef<Path>>(pid_file: P) -> ServerState {
+    let pid_file = pid_file.as_ref();
+    let state = read_server_state(pid_file);
+    debug!(
+        "pid_file.read",
+        path = pid_file.display(),
+        state = format!("{:?}", state)
+    );
+    return state;
+}
+
+fn read_server_state(pid_file: &Path) -> ServerState {
+    if !pid_file.is_file() {
+        return ServerState::NoPidFile;
+    }
+
+    let pid = fs::read_to_string(pid_file)
+        .ok()
+        .and_then(|text| text.trim().parse::<i32>().ok())
+        // 0 and negative numbers would refer to process groups rather than a process
+        .filter(|pid| *pid > 0)
+        .map(Pid::from_raw);
+    let pid = match pid {
+        Some(pid) => pid,
+        None => return ServerState::NotRunning,
+    };
+
+    // Only ESRCH means there's no such process, EPERM means it's running as another user
+    return match kill(pid, None) {
+        Err(nix::Error::Sys(ESRCH)) => ServerState::StalePidFile { pid },
+        _ => ServerState::Running { pid },
+    };
+}
+
+fn remove_stale_files(pid_file: &Path, sock_file: &Path) {
+    eprintln!(
+        "The server is not running, it may have crashed. Removing stale PID file {} and socket \
+         file {}",
+        pid_file.display(),
+        sock_file.display()
+    );
+    let _ = fs::remove_file(pid_file);
+    let _ = fs::remove_file(sock_file);
 }
 
 pub fn tps_cap(tps: f64) -> f64 {
     return tps.min(20.0);
 }
 
+/// Formats a number of bytes using the largest binary unit which fits, e.g. `2147483648` is
+/// `2.0 GiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    return format!("{:.1} {}", value, UNITS[unit]);
+}
+
+/// Parses a duration such as `90`, `30s`, `5m` or `1h`. Plain numbers are seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, ParseIntError> {
+    let text = text.trim();
+    let (number, multiplier) = match text.chars().last() {
+        Some('s') => (&text[..text.len() - 1], 1),
+        Some('m') => (&text[..text.len() - 1], 60),
+        Some('h') => (&text[..text.len() - 1], 3600),
+        _ => (text, 1),
+    };
+
+    return match number.parse::<u64>()?.checked_mul(multiplier) {
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        // ParseIntError can't be created directly, but parsing a number one past the largest u64
+        // gives the same "number too large" error
+        None => Err("18446744073709551616".parse::<u64>().unwrap_err()),
+    };
+}
+
+/// The current time as a local date and time of day. If the local time zone can't be worked out,
+/// the time is given in UTC instead.
+pub fn local_tm() -> libc::tm {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as libc::time_t)
+        .unwrap_or(0);
+
+    // localtime_r and gmtime_r only write to the given struct, and are thread safe unlike
+    // localtime and gmtime
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        unsafe { libc::gmtime_r(&now, &mut tm) };
+    }
+    return tm;
+}
+
+/// Draws a series of TPS samples as a line of block characters, one per sample. Full blocks are
+/// 20 TPS, the lowest block is 0.
+pub fn tps_sparkline(samples: &[f64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    return samples
+        .iter()
+        .map(|tps| {
+            let fraction = tps_cap(*tps).max(0.0) / 20.0;
+            return BLOCKS[(fraction * (BLOCKS.len() - 1) as f64).round() as usize];
+        })
+        .collect();
+}
+
 #[derive(Clone)]
 pub enum ExitValue {
     Code(i32),
@@ -145,3 +478,35 @@ impl<T> ExitError<T> for Result<T, paperd_lib::Error> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("10").ok(), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("10s").ok(), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("5m").ok(), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration(" 2h ").ok(), Some(Duration::from_secs(7200)));
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1d").is_err());
+    }
+
+    #[test]
+    fn parse_duration_overflow() {
+        assert_eq!(
+            parse_duration("18446744073709551615s").ok(),
+            Some(Duration::from_secs(u64::max_value()))
+        );
+        let err = parse_duration("18446744073709551615m").err().unwrap();
+        assert_eq!(err, "18446744073709551616".parse::<u64>().unwrap_err());
+        assert!(parse_duration("18446744073709551616").is_err());
+    }
+}
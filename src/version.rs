@@ -0,0 +1,48 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::client::Client;
+use crate::util::{get_sock, ExitValue};
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+
+pub fn version(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    if !sub_m.is_present("SERVER") {
+        println!("paperd {}", crate_version!());
+        return Ok(());
+    }
+
+    let (sock, sock_file) = get_sock(sub_m)?;
+    let client = Client::from_socket(sock, &sock_file)?;
+
+    let res = client.server_version()?;
+    println!("Minecraft version: {}", res.minecraft_version);
+    println!("Paper version:     {}", res.paper_version);
+
+    return Ok(());
+}
+
+// Request
+#[derive(Serialize)]
+pub struct ServerVersionMessage {}
+
+// Response
+#[derive(Serialize, Deserialize)]
+pub struct ServerVersionMessageResponse {
+    #[serde(rename = "minecraftVersion")]
+    pub minecraft_version: String,
+    #[serde(rename = "paperVersion")]
+    pub paper_version: String,
+}
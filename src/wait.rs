@@ -0,0 +1,105 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use crate::info;
+use crate::runner::EXIT_FILE_NAME;
+use crate::util::{
+    find_pid_file, get_sock_from_file_direct, server_state, sock_file_path, ExitError, ExitValue,
+    ServerState,
+};
+use crate::verbose;
+use clap::ArgMatches;
+use nix::errno::Errno::ESRCH;
+use nix::sys::signal::kill;
+use nix::Error;
+use std::fs;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What `wait` exits with when `--timeout` runs out, the same as coreutils' `timeout` uses. Any
+/// other code is the server's.
+pub const WAIT_TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Blocks until the server has stopped for good, then exits with the exit code paperd recorded for
+/// it. Restarts don't count as stopping, the paperd process in the PID file stays alive across
+/// those.
+pub fn wait(sub_m: &ArgMatches) -> Result<(), ExitValue> {
+    let timeout = match sub_m.value_of("TIMEOUT") {
+        Some(t) => Some(Duration::from_secs(
+            t.parse::<u64>().conv("Failed to parse --timeout")?,
+        )),
+        None => None,
+    };
+
+    let sock_file = sock_file_path(sub_m)?;
+    let pid_file = find_pid_file(&sock_file);
+    let exit_file = sock_file
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join(EXIT_FILE_NAME);
+
+    // The server is a child of the paperd process in the PID file rather than of us, so it can't be
+    // waited on directly, only polled
+    let pid = match pid_file.as_ref().map(server_state) {
+        Some(ServerState::Running { pid }) => Some(pid),
+        _ => None,
+    };
+    let is_running = || match pid {
+        Some(pid) => kill(pid, None) != Err(Error::Sys(ESRCH)),
+        // Servers not started by paperd have no PID file, but are still running while the
+        // socket accepts connections
+        None => get_sock_from_file_direct(&sock_file).is_ok(),
+    };
+
+    if is_running() {
+        match pid {
+            Some(pid) => info!("Waiting for the server to stop. PID: {}", pid),
+            None => info!("Waiting for the server to stop"),
+        }
+
+        let start = Instant::now();
+        while is_running() {
+            if timeout.map_or(false, |t| start.elapsed() >= t) {
+                eprintln!("Timeout while waiting for the server to stop");
+                return Err(ExitValue::Code(WAIT_TIMEOUT_EXIT_CODE));
+            }
+            sleep(POLL_INTERVAL);
+        }
+        info!("Server stopped");
+    } else {
+        info!("The server isn't running");
+    }
+
+    return match read_exit_code(&exit_file) {
+        Some(0) => Ok(()),
+        Some(code) => {
+            verbose!("Server exited with code {}", code);
+            Err(ExitValue::Code(code))
+        }
+        None => {
+            verbose!("No exit code recorded in {}", exit_file.display());
+            Ok(())
+        }
+    };
+}
+
+fn read_exit_code(exit_file: &Path) -> Option<i32> {
+    return fs::read_to_string(exit_file)
+        .ok()
+        .and_then(|text| text.trim().parse::<i32>().ok());
+}
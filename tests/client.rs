@@ -0,0 +1,90 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reads a server's status through `Client`, with the server's responses queued up on a socket
+//! pair before the client asks for them.
+
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use paperd::client::{Client, PROTOCOL_VERSION};
+use paperd::messaging::MessageSocket;
+use paperd_lib::{receive_message, send_message, Message, MessageHeader, Socket};
+use std::fmt::Display;
+
+fn check<T, E: Display>(res: Result<T, E>) -> T {
+    return match res {
+        Ok(t) => t,
+        Err(e) => panic!("{}", e),
+    };
+}
+
+fn respond(peer: Socket, message_type: i64, text: &str) {
+    let message = Message {
+        header: MessageHeader {
+            message_type,
+            message_length: text.len() as i64,
+        },
+        message_text: text.to_string(),
+    };
+    check(send_message(peer, &message));
+}
+
+#[test]
+fn status_tps_and_memory() {
+    let (ours, peer) = socketpair(
+        AddressFamily::Unix,
+        SockType::Stream,
+        None,
+        SockFlag::empty(),
+    )
+    .unwrap();
+
+    respond(
+        peer,
+        0,
+        &format!(r#"{{"protocolVersion":{}}}"#, PROTOCOL_VERSION),
+    );
+    respond(
+        peer,
+        3,
+        r#"{
+            "motd": "A Minecraft Server",
+            "serverName": "Paper",
+            "serverVersion": "git-Paper-123",
+            "apiVersion": "1.15.2-R0.1-SNAPSHOT",
+            "players": ["DemonWav"],
+            "worlds": [],
+            "tps": {"oneMin": 19.5, "fiveMin": 19.75, "fifteenMin": 20.0},
+            "memoryUsage": {"usedMemory": "512 MB", "totalMemory": "1024 MB", "maxMemory": "2048 MB"}
+        }"#,
+    );
+
+    let client = check(Client::from_socket(MessageSocket::new(ours), "paperd.sock"));
+    let status = check(client.status());
+
+    assert_eq!(status.tps.one_min, 19.5);
+    assert_eq!(status.tps.five_min, 19.75);
+    assert_eq!(status.tps.fifteen_min, 20.0);
+    assert!(status.tps.history.is_empty());
+    assert_eq!(status.memory_usage.used_memory, "512 MB");
+    assert_eq!(status.memory_usage.max_memory, "2048 MB");
+    assert_eq!(status.players, vec!["DemonWav".to_string()]);
+    assert!(status.uptime.is_none());
+
+    // The client asked for the protocol version first, then the status
+    let protocol = check(receive_message(peer)).unwrap();
+    assert_eq!(protocol.header.message_type, 0);
+    let request = check(receive_message(peer)).unwrap();
+    assert_eq!(request.header.message_type, 3);
+}
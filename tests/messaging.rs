@@ -0,0 +1,110 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks that `MessageSocket` reports failures as typed errors, over a socket pair rather than a
+//! real server.
+
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use paperd::messages::MessageHandler;
+use paperd::messages::PingMessage;
+use paperd::messaging::{MessageError, MessageSocket};
+use paperd_lib::{receive_message, send_message, Message, MessageHeader, Socket};
+use serde::ser::Error;
+use serde::{Serialize, Serializer};
+use std::fmt::Display;
+
+fn check<T, E: Display>(res: Result<T, E>) -> T {
+    return match res {
+        Ok(t) => t,
+        Err(e) => panic!("{}", e),
+    };
+}
+
+fn socket_pair() -> (MessageSocket, Socket) {
+    let (ours, theirs) = socketpair(
+        AddressFamily::Unix,
+        SockType::Stream,
+        None,
+        SockFlag::empty(),
+    )
+    .unwrap();
+    return (MessageSocket::new(ours), theirs);
+}
+
+/// A message which always fails to serialize.
+struct BrokenMessage;
+
+impl MessageHandler for BrokenMessage {
+    fn type_id() -> i64 {
+        return 1000;
+    }
+}
+
+impl Serialize for BrokenMessage {
+    fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+        return Err(S::Error::custom("broken on purpose"));
+    }
+}
+
+#[test]
+fn serialize_error() {
+    let (sock, peer) = socket_pair();
+
+    match sock.send_message(&BrokenMessage) {
+        Err(MessageError::Serialize(e)) => assert!(e.to_string().contains("broken on purpose")),
+        Err(e) => panic!("Wrong error: {}", e),
+        Ok(()) => panic!("Message was sent"),
+    }
+
+    // Nothing may have been written, not even the header, or the next message would be garbled
+    drop(sock);
+    assert!(check(receive_message(peer)).is_none());
+}
+
+#[test]
+fn send_message_type() {
+    let (sock, peer) = socket_pair();
+
+    check(sock.send_message(&PingMessage { payload: 42 }));
+
+    let message = check(receive_message(peer)).unwrap();
+    assert_eq!(message.header.message_type, PingMessage::type_id());
+    assert_eq!(message.message_text, r#"{"payload":42}"#);
+}
+
+#[test]
+fn message_too_large() {
+    let (mut sock, peer) = socket_pair();
+    sock.set_max_message_length(4);
+
+    let text = r#"{"payload":42}"#;
+    let message = Message {
+        header: MessageHeader {
+            message_type: PingMessage::type_id(),
+            message_length: text.len() as i64,
+        },
+        message_text: text.to_string(),
+    };
+    check(send_message(peer, &message));
+
+    match sock.receive_message::<serde_json::Value>() {
+        Err(MessageError::TooLarge(length, max)) => {
+            assert_eq!(length, text.len() as i64);
+            assert_eq!(max, 4);
+        }
+        Err(e) => panic!("Wrong error: {}", e),
+        Ok(_) => panic!("Message was received"),
+    }
+}
@@ -0,0 +1,89 @@
+// This file is part of paperd, the PaperMC server daemon
+// Copyright (C) 2019 Kyle Wood (DemonWav)
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, version 3 only.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Checks `server_state` against fake PID files, one for each state a server can be in.
+
+use nix::unistd::Pid;
+use paperd::{server_state, ServerState};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes `text` to a new PID file, returning its path.
+fn pid_file(text: &str) -> PathBuf {
+    let count = FILE_COUNT.fetch_add(1, Ordering::SeqCst);
+    let path = env::temp_dir().join(format!("paperd-test-{}-{}.pid", process::id(), count));
+    fs::write(&path, text).unwrap();
+    return path;
+}
+
+fn check(text: &str, expected: ServerState) {
+    let path = pid_file(text);
+    let state = server_state(&path);
+    let _ = fs::remove_file(&path);
+    assert_eq!(state, expected);
+}
+
+#[test]
+fn running() {
+    let pid = process::id() as i32;
+    check(
+        pid.to_string().as_str(),
+        ServerState::Running {
+            pid: Pid::from_raw(pid),
+        },
+    );
+    // PID files may end with a newline
+    check(
+        format!("{}\n", pid).as_str(),
+        ServerState::Running {
+            pid: Pid::from_raw(pid),
+        },
+    );
+}
+
+#[test]
+fn stale_pid_file() {
+    // Once the child has exited and been waited for, its PID doesn't belong to any process
+    let mut child = Command::new("true").spawn().unwrap();
+    let pid = child.id() as i32;
+    child.wait().unwrap();
+
+    check(
+        pid.to_string().as_str(),
+        ServerState::StalePidFile {
+            pid: Pid::from_raw(pid),
+        },
+    );
+}
+
+#[test]
+fn not_running() {
+    check("", ServerState::NotRunning);
+    check("not a pid", ServerState::NotRunning);
+    // These would refer to process groups
+    check("0", ServerState::NotRunning);
+    check("-1", ServerState::NotRunning);
+}
+
+#[test]
+fn no_pid_file() {
+    let path = env::temp_dir().join(format!("paperd-test-{}-missing.pid", process::id()));
+    assert_eq!(server_state(&path), ServerState::NoPidFile);
+}